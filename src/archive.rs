@@ -0,0 +1,618 @@
+//! Directories as tar or zip archives, built while they are sent
+
+use crate::{
+    common::{percent_decode, percent_encode},
+    compression::crc32_update,
+    date::{civil_from_days, unix_seconds},
+    path::{DotFiles, PathPolicy},
+    request::{Body, Request},
+    response::Response,
+};
+use std::{
+    fs::{File, Metadata},
+    io::{self, Cursor, Read, Seek},
+    path::{Path, PathBuf},
+};
+
+const BLOCK: usize = 512;
+/// Largest value of the 11 digit octal fields in a tar header
+const MAX_OCTAL: u64 = 0o777_7777_7777;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// Archive format of a directory download
+pub(crate) enum Format {
+    /// POSIX tar, with pax headers for long names and large files
+    Tar,
+    /// Zip with stored (uncompressed) entries
+    Zip,
+}
+
+impl Format {
+    /// Format for the value of a `?download=` search param
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "tar" => Some(Self::Tar),
+            "zip" => Some(Self::Zip),
+            _ => None,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Tar => "tar",
+            Self::Zip => "zip",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Tar => "application/x-tar",
+            Self::Zip => "application/zip",
+        }
+    }
+}
+
+/// Reads exactly `remaining` bytes, padding with zeros if the file got shorter in the meantime
+struct Exact<R> {
+    inner: R,
+    remaining: u64,
+}
+
+impl<R: Read> Read for Exact<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let max = buf
+            .len()
+            .min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        if max == 0 {
+            return Ok(0);
+        }
+
+        let n = match self.inner.read(&mut buf[..max])? {
+            0 => {
+                buf[..max].fill(0);
+                max
+            }
+            n => n,
+        };
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+/// Central directory entry of a zip file
+struct ZipRecord {
+    name: String,
+    crc: u32,
+    size: u32,
+    offset: u32,
+    modified: (u16, u16),
+    is_dir: bool,
+}
+
+/// Reader producing the archive of a directory tree, one entry at a time
+struct Archive {
+    format: Format,
+    /// Canonical served directory, symlinks may not leave it unless the policy allows it
+    root: PathBuf,
+    policy: PathPolicy,
+    /// Entries still to be archived with their names inside the archive, used as a stack
+    pending: Vec<(PathBuf, String)>,
+    /// Canonical directories archived so far, so symlinks can not cause loops
+    visited: Vec<PathBuf>,
+    current: Box<dyn Read + Send>,
+    /// Bytes produced before [`Archive::current`]
+    offset: u64,
+    records: Vec<ZipRecord>,
+    finished: bool,
+}
+
+impl Archive {
+    fn new(
+        format: Format,
+        root: &Path,
+        dir: &Path,
+        name: &str,
+        policy: &PathPolicy,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            format,
+            root: root.canonicalize()?,
+            policy: policy.clone(),
+            pending: vec![(dir.to_path_buf(), name.to_string())],
+            visited: Vec::new(),
+            current: Box::new(io::empty()),
+            offset: 0,
+            records: Vec::new(),
+            finished: false,
+        })
+    }
+
+    /// The next file or directory that may be archived, skipping anything the policy hides
+    fn next_entry(&mut self) -> io::Result<Option<Box<dyn Read + Send>>> {
+        while let Some((path, name)) = self.pending.pop() {
+            // dangling symlinks and files that disappeared are left out
+            let (Ok(metadata), Ok(link)) = (path.metadata(), path.symlink_metadata()) else {
+                continue;
+            };
+            if link.is_symlink()
+                && !self.policy.external_symlinks
+                && !path.canonicalize()?.starts_with(&self.root)
+            {
+                continue;
+            }
+
+            if metadata.is_dir() {
+                let canonical = path.canonicalize()?;
+                if self.visited.contains(&canonical) {
+                    continue;
+                }
+                self.visited.push(canonical);
+
+                let mut children = std::fs::read_dir(&path)?
+                    .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                    .filter(|child| {
+                        self.policy.dotfiles == DotFiles::Allow || !child.starts_with('.')
+                    })
+                    .collect::<Vec<_>>();
+                children.sort_by(|a, b| b.cmp(a));
+                self.pending.extend(
+                    children
+                        .into_iter()
+                        .map(|child| (path.join(&child), format!("{name}/{child}"))),
+                );
+
+                return self.directory(&format!("{name}/"), &metadata).map(Some);
+            } else if metadata.is_file() {
+                match File::open(&path) {
+                    Ok(file) => return self.file(file, &name, &metadata).map(Some),
+                    Err(..) => continue,
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn directory(&mut self, name: &str, metadata: &Metadata) -> io::Result<Box<dyn Read + Send>> {
+        let header = match self.format {
+            Format::Tar => tar_header(name, 0, metadata, b'5'),
+            Format::Zip => self.zip_header(name, 0, 0, metadata)?,
+        };
+        self.offset += header.len() as u64;
+        Ok(Box::new(Cursor::new(header)))
+    }
+
+    fn file(
+        &mut self,
+        mut file: File,
+        name: &str,
+        metadata: &Metadata,
+    ) -> io::Result<Box<dyn Read + Send>> {
+        match self.format {
+            Format::Tar => {
+                let size = metadata.len();
+                let header = tar_header(name, size, metadata, b'0');
+                let padding = (BLOCK - (size % BLOCK as u64) as usize) % BLOCK;
+                self.offset += header.len() as u64 + size + padding as u64;
+
+                Ok(Box::new(
+                    Cursor::new(header)
+                        .chain(Exact {
+                            inner: file,
+                            remaining: size,
+                        })
+                        .chain(Cursor::new(vec![0; padding])),
+                ))
+            }
+            Format::Zip => {
+                // the checksum goes in front of the data, so the file is read twice
+                let (mut crc, mut size) = (0, 0u64);
+                let mut buf = vec![0; 64 * 1024];
+                loop {
+                    match file.read(&mut buf)? {
+                        0 => break,
+                        n => {
+                            crc = crc32_update(crc, &buf[..n]);
+                            size += n as u64;
+                        }
+                    }
+                }
+                file.rewind()?;
+
+                let header = self.zip_header(name, crc, size, metadata)?;
+                self.offset += header.len() as u64 + size;
+
+                Ok(Box::new(Cursor::new(header).chain(Exact {
+                    inner: file,
+                    remaining: size,
+                })))
+            }
+        }
+    }
+
+    /// Local file header of a zip entry, also remembering it for the central directory
+    fn zip_header(
+        &mut self,
+        name: &str,
+        crc: u32,
+        size: u64,
+        metadata: &Metadata,
+    ) -> io::Result<Vec<u8>> {
+        let too_large = || io::Error::other("directory too large for a zip archive");
+        let record = ZipRecord {
+            name: name.to_string(),
+            crc,
+            size: u32::try_from(size).map_err(|_| too_large())?,
+            offset: u32::try_from(self.offset).map_err(|_| too_large())?,
+            modified: dos_time(metadata),
+            is_dir: metadata.is_dir(),
+        };
+
+        let mut header = Vec::with_capacity(30 + name.len());
+        header.extend(0x0403_4b50u32.to_le_bytes());
+        header.extend(zip_common(&record));
+        header.extend(0u16.to_le_bytes());
+        header.extend(name.as_bytes());
+
+        self.records.push(record);
+        Ok(header)
+    }
+
+    /// What follows the last entry
+    fn end(&mut self) -> io::Result<Box<dyn Read + Send>> {
+        match self.format {
+            Format::Tar => Ok(Box::new(Cursor::new(vec![0; 2 * BLOCK]))),
+            Format::Zip => {
+                let too_large = || io::Error::other("directory too large for a zip archive");
+                let mut out = Vec::new();
+
+                for record in &self.records {
+                    out.extend(0x0201_4b50u32.to_le_bytes());
+                    // made by unix, zip 2.0
+                    out.extend((3u16 << 8 | 20).to_le_bytes());
+                    out.extend(zip_common(record));
+                    // extra field, comment, disk number, internal attributes
+                    out.extend([0; 8]);
+                    let mode: u32 = if record.is_dir { 0o040755 } else { 0o100644 };
+                    out.extend((mode << 16 | u32::from(record.is_dir) << 4).to_le_bytes());
+                    out.extend(record.offset.to_le_bytes());
+                    out.extend(record.name.as_bytes());
+                }
+
+                let count = u16::try_from(self.records.len()).map_err(|_| too_large())?;
+                let size = u32::try_from(out.len()).map_err(|_| too_large())?;
+                let offset = u32::try_from(self.offset).map_err(|_| too_large())?;
+                out.extend(0x0605_4b50u32.to_le_bytes());
+                out.extend([0; 4]);
+                out.extend(count.to_le_bytes());
+                out.extend(count.to_le_bytes());
+                out.extend(size.to_le_bytes());
+                out.extend(offset.to_le_bytes());
+                out.extend([0; 2]);
+
+                Ok(Box::new(Cursor::new(out)))
+            }
+        }
+    }
+}
+
+impl Read for Archive {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.current.read(buf)?;
+            if n > 0 || buf.is_empty() || self.finished {
+                return Ok(n);
+            }
+
+            self.current = match self.next_entry()? {
+                Some(entry) => entry,
+                None => {
+                    self.finished = true;
+                    self.end()?
+                }
+            };
+        }
+    }
+}
+
+/// Fields shared by the local and central zip headers, from the version needed to the name length
+fn zip_common(record: &ZipRecord) -> Vec<u8> {
+    let mut out = Vec::with_capacity(24);
+    out.extend(20u16.to_le_bytes());
+    // names are UTF-8
+    out.extend(0x0800u16.to_le_bytes());
+    // stored
+    out.extend(0u16.to_le_bytes());
+    out.extend(record.modified.0.to_le_bytes());
+    out.extend(record.modified.1.to_le_bytes());
+    out.extend(record.crc.to_le_bytes());
+    out.extend(record.size.to_le_bytes());
+    out.extend(record.size.to_le_bytes());
+    out.extend((record.name.len() as u16).to_le_bytes());
+    out
+}
+
+/// MS-DOS `(time, date)` of the modification time, which can not go before 1980
+fn dos_time(metadata: &Metadata) -> (u16, u16) {
+    let secs = metadata.modified().map(unix_seconds).unwrap_or(0) as i64;
+    let (year, month, day) = civil_from_days(secs / 86_400);
+    if year < 1980 {
+        return (0, 1 << 5 | 1);
+    }
+
+    let secs = secs % 86_400;
+    let time = (secs / 3600) << 11 | (secs / 60 % 60) << 5 | (secs % 60 / 2);
+    let date = (year.min(2107) - 1980) << 9 | i64::from(month) << 5 | i64::from(day);
+    (time as u16, date as u16)
+}
+
+/// A single `key=value` record of a pax extended header, prefixed by its own length
+fn pax_record(key: &str, value: &str) -> String {
+    let record = format!(" {key}={value}\n");
+    let mut length = record.len();
+    while length != record.len() + length.to_string().len() {
+        length = record.len() + length.to_string().len();
+    }
+    format!("{length}{record}")
+}
+
+/// ustar header block(s) for an entry, preceded by a pax header if the name or size do not fit
+fn tar_header(name: &str, size: u64, metadata: &Metadata, typeflag: u8) -> Vec<u8> {
+    let modified = metadata.modified().map(unix_seconds).unwrap_or(0);
+    let mut out = Vec::new();
+
+    let mut pax = String::new();
+    if name.len() > 100 {
+        pax += &pax_record("path", name);
+    }
+    if size > MAX_OCTAL {
+        pax += &pax_record("size", &size.to_string());
+    }
+    if !pax.is_empty() {
+        out.extend(ustar_block(
+            "././@PaxHeader",
+            pax.len() as u64,
+            modified,
+            b'x',
+        ));
+        out.extend(pax.as_bytes());
+        out.resize(out.len().div_ceil(BLOCK) * BLOCK, 0);
+    }
+
+    out.extend(ustar_block(name, size, modified, typeflag));
+    out
+}
+
+fn ustar_block(name: &str, size: u64, modified: u64, typeflag: u8) -> [u8; BLOCK] {
+    let mut block = [0; BLOCK];
+    let mut field = |offset: usize, value: &[u8]| {
+        block[offset..offset + value.len()].copy_from_slice(value);
+    };
+
+    // longer names are in the pax header
+    field(0, &name.as_bytes()[..name.len().min(100)]);
+    let mode = if typeflag == b'5' { 0o755 } else { 0o644 };
+    field(100, format!("{mode:07o}\0").as_bytes());
+    field(108, b"0000000\0");
+    field(116, b"0000000\0");
+    field(124, format!("{:011o}\0", size.min(MAX_OCTAL)).as_bytes());
+    field(
+        136,
+        format!("{:011o}\0", modified.min(MAX_OCTAL)).as_bytes(),
+    );
+    field(148, b"        ");
+    field(156, &[typeflag]);
+    field(257, b"ustar\0");
+    field(263, b"00");
+
+    let checksum = block.iter().map(|&byte| u32::from(byte)).sum::<u32>();
+    block[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+    block
+}
+
+/// Stream the directory at `dir` inside the served directory `root` as an archive.
+///
+/// Entries are named after the directory, dotfiles and symlinks follow `policy`.
+pub(crate) fn download(
+    root: &Path,
+    dir: &Path,
+    req: &Request,
+    format: Format,
+    policy: &PathPolicy,
+) -> Response {
+    let name = req
+        .pathname
+        .split('/')
+        .rfind(|segment| !segment.is_empty())
+        .and_then(percent_decode)
+        .and_then(|name| String::from_utf8(name).ok())
+        .or_else(|| {
+            root.canonicalize()
+                .ok()?
+                .file_name()?
+                .to_str()
+                .map(str::to_string)
+        })
+        .unwrap_or("download".to_string());
+
+    let archive = match Archive::new(format, root, dir, &name, policy) {
+        Ok(archive) => archive,
+        Err(..) => return Response::builder().status(500).build(),
+    };
+
+    let filename = format!("{name}.{}", format.extension());
+    let fallback = filename
+        .chars()
+        .map(|c| {
+            if c.is_ascii_graphic() && c != '"' && c != '\\' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+
+    Response::builder()
+        .status(200)
+        .header("Content-Type", format.content_type())
+        .header(
+            "Content-Disposition",
+            format!(
+                "attachment; filename=\"{fallback}\"; filename*=UTF-8''{}",
+                percent_encode(&filename)
+            ),
+        )
+        .body(Body::Stream(Box::new(archive)))
+        .build()
+}
+
+#[cfg(test)]
+/// Root with a `tree` to archive, holding a dotfile, a name too long for plain tar headers, a
+/// symlink out of the root and one looping back into the tree
+fn test_tree() -> (crate::test_utils::TempDir, PathBuf) {
+    let (dir, root) = crate::test_utils::temp_root("archive");
+    std::fs::create_dir_all(root.join("tree/sub")).unwrap();
+    std::fs::write(root.join("tree/a.txt"), "a").unwrap();
+    std::fs::write(root.join("tree/.env"), "secret").unwrap();
+    std::fs::write(root.join("tree/sub/b.txt"), "b".repeat(1000)).unwrap();
+    std::fs::write(root.join(format!("tree/sub/{}", "long".repeat(40))), "").unwrap();
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::symlink;
+        symlink(dir.join("outside"), root.join("tree/escape")).unwrap();
+        symlink(root.join("tree"), root.join("tree/sub/loop")).unwrap();
+    }
+
+    (dir, root)
+}
+
+#[cfg(test)]
+fn download_body(root: &Path, raw: &str) -> (String, Vec<u8>) {
+    use crate::{
+        handlers::{fs_handler_with, FsOptions, IndexStyle},
+        server::Server,
+        test_utils::exchange_bytes,
+    };
+
+    let handler = fs_handler_with(
+        root.to_str().unwrap(),
+        FsOptions::new(IndexStyle::IndexDirectory).archives(true),
+    );
+    let server = Server::new().not_found(&*handler);
+    let response = exchange_bytes(&server, format!("GET {raw} HTTP/1.0\r\n\r\n").as_bytes());
+    let end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    (
+        String::from_utf8_lossy(&response[..end]).into_owned(),
+        response[end + 4..].to_vec(),
+    )
+}
+
+#[test]
+fn test_tar_download() {
+    let (_dir, root) = test_tree();
+    let (head, body) = download_body(&root, "/tree/?download=tar");
+    assert!(head.contains("Content-Type: application/x-tar"));
+    assert!(head.contains("Content-Disposition: attachment; filename=\"tree.tar\""));
+
+    let mut entries = Vec::new();
+    let mut path = None;
+    let mut offset = 0;
+    while body[offset..offset + BLOCK] != [0; BLOCK] {
+        let header = &body[offset..offset + BLOCK];
+        let checksum = header
+            .iter()
+            .enumerate()
+            .map(|(i, &byte)| {
+                if (148..156).contains(&i) {
+                    32
+                } else {
+                    u32::from(byte)
+                }
+            })
+            .sum::<u32>();
+        assert_eq!(format!("{checksum:06o}\0 ").as_bytes(), &header[148..156]);
+
+        let octal = |field: &[u8]| {
+            u64::from_str_radix(std::str::from_utf8(&field[..11]).unwrap(), 8).unwrap()
+        };
+        let size = octal(&header[124..136]) as usize;
+        let data = &body[offset + BLOCK..offset + BLOCK + size];
+        offset += BLOCK + size.div_ceil(BLOCK) * BLOCK;
+
+        if header[156] == b'x' {
+            let record = std::str::from_utf8(data).unwrap();
+            path = Some(record.split_once("path=").unwrap().1.trim_end().to_string());
+            continue;
+        }
+        let name = path.take().unwrap_or_else(|| {
+            String::from_utf8_lossy(&header[..100])
+                .trim_end_matches('\0')
+                .to_string()
+        });
+        entries.push((name, data.to_vec()));
+    }
+    assert_eq!(body.len(), offset + 2 * BLOCK);
+
+    let long = format!("tree/sub/{}", "long".repeat(40));
+    let b = "b".repeat(1000);
+    assert_eq!(
+        entries
+            .iter()
+            .map(|(name, data)| (name.as_str(), data.as_slice()))
+            .collect::<Vec<_>>(),
+        [
+            ("tree/", &b""[..]),
+            ("tree/a.txt", b"a"),
+            ("tree/sub/", b""),
+            ("tree/sub/b.txt", b.as_bytes()),
+            (long.as_str(), b""),
+        ]
+    );
+}
+
+#[test]
+fn test_zip_download() {
+    use crate::compression::crc32;
+
+    let (_dir, root) = test_tree();
+    let (head, body) = download_body(&root, "/tree/?download=zip");
+    assert!(head.contains("Content-Type: application/zip"));
+
+    let u16_at = |offset: usize| u16::from_le_bytes([body[offset], body[offset + 1]]) as usize;
+    let u32_at = |offset: usize| u32::from_le_bytes(body[offset..offset + 4].try_into().unwrap());
+
+    let end = body.len() - 22;
+    assert_eq!(u32_at(end), 0x0605_4b50);
+    let count = u16_at(end + 10);
+    let mut offset = u32_at(end + 16) as usize;
+
+    let mut names = Vec::new();
+    for _ in 0..count {
+        assert_eq!(u32_at(offset), 0x0201_4b50);
+        let (crc, size) = (u32_at(offset + 16), u32_at(offset + 20) as usize);
+        let name_length = u16_at(offset + 28);
+        let name =
+            String::from_utf8(body[offset + 46..offset + 46 + name_length].to_vec()).unwrap();
+
+        let local = u32_at(offset + 42) as usize;
+        assert_eq!(u32_at(local), 0x0403_4b50);
+        assert_eq!(&body[local + 30..local + 30 + name_length], name.as_bytes());
+        let data = &body[local + 30 + name_length..local + 30 + name_length + size];
+        assert_eq!(crc32(data), crc);
+
+        names.push((name, String::from_utf8(data.to_vec()).unwrap()));
+        offset += 46 + name_length;
+    }
+
+    assert_eq!(
+        names,
+        [
+            ("tree/".to_string(), String::new()),
+            ("tree/a.txt".to_string(), "a".to_string()),
+            ("tree/sub/".to_string(), String::new()),
+            ("tree/sub/b.txt".to_string(), "b".repeat(1000)),
+            (format!("tree/sub/{}", "long".repeat(40)), String::new()),
+        ]
+    );
+}
@@ -0,0 +1,194 @@
+//! `Cache-Control` rules for static files
+
+/// What a [`CacheRules`] rule matches
+enum Matcher {
+    /// Glob matched against the path of the file, see [`CacheRules::glob`]
+    Glob(String),
+    /// MIME type, `type/*` matches a whole type
+    MimeType(String),
+}
+
+#[derive(Default)]
+/// `Cache-Control` values for files by path or MIME type, the first matching rule is used
+///
+/// # Examples
+/// ```
+/// # use rust_http_server::cache::CacheRules;
+/// let rules = CacheRules::new()
+///     .glob("*.js", "max-age=31536000, immutable")
+///     .glob("*.css", "max-age=31536000, immutable")
+///     .glob("*.html", "no-cache")
+///     .mime_type("image/*", "max-age=86400");
+///
+/// assert_eq!(rules.get("/assets/app.js", "text/javascript"), Some("max-age=31536000, immutable"));
+/// assert_eq!(rules.get("/logo.png", "image/png"), Some("max-age=86400"));
+/// assert_eq!(rules.get("/data.json", "application/json"), None);
+/// ```
+pub struct CacheRules {
+    rules: Vec<(Matcher, String)>,
+}
+
+impl CacheRules {
+    /// No rules, files get no `Cache-Control` header
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use `value` for files whose path matches `pattern`.
+    ///
+    /// `*` matches anything but `/`, `**` matches anything and `?` matches a single character.
+    /// Patterns without a `/` only look at the file name, others at the whole path from the
+    /// served directory, e.g. `/assets/**`.
+    pub fn glob(mut self, pattern: impl ToString, value: impl ToString) -> Self {
+        self.rules
+            .push((Matcher::Glob(pattern.to_string()), value.to_string()));
+        self
+    }
+
+    /// Use `value` for files of `mime_type`, `type/*` matches a whole type
+    pub fn mime_type(mut self, mime_type: impl ToString, value: impl ToString) -> Self {
+        self.rules.push((
+            Matcher::MimeType(mime_type.to_string().to_lowercase()),
+            value.to_string(),
+        ));
+        self
+    }
+
+    /// Add a rule written as `pattern=value`, MIME types are written as `type:text/html=value`
+    ///
+    /// # Examples
+    /// ```
+    /// # use rust_http_server::cache::CacheRules;
+    /// let rules = CacheRules::new()
+    ///     .rule("*.html=no-cache")
+    ///     .unwrap()
+    ///     .rule("type:image/*=max-age=86400")
+    ///     .unwrap();
+    ///
+    /// assert_eq!(rules.get("/index.html", "text/html"), Some("no-cache"));
+    /// assert_eq!(rules.get("/a.png", "image/png"), Some("max-age=86400"));
+    /// assert!(CacheRules::new().rule("no-value").is_none());
+    /// ```
+    pub fn rule(self, rule: &str) -> Option<Self> {
+        let (pattern, value) = rule.split_once('=')?;
+        match pattern.strip_prefix("type:") {
+            Some(mime_type) => Some(self.mime_type(mime_type, value.trim())),
+            None => Some(self.glob(pattern, value.trim())),
+        }
+    }
+
+    /// `Cache-Control` value for the file at `path` (relative to the served directory, starting
+    /// with `/`) of `mime_type`
+    pub fn get(&self, path: &str, mime_type: &str) -> Option<&str> {
+        let file_name = path.rsplit('/').next().unwrap_or_default();
+        let essence = mime_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+
+        self.rules
+            .iter()
+            .find(|(matcher, _)| match matcher {
+                Matcher::Glob(pattern) if pattern.contains('/') => glob_match(pattern, path),
+                Matcher::Glob(pattern) => glob_match(pattern, file_name),
+                Matcher::MimeType(rule) => match rule.strip_suffix("/*") {
+                    Some(kind) => essence.split('/').next() == Some(kind),
+                    None => essence == *rule,
+                },
+            })
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Match `text` against a glob `pattern`, see [`CacheRules::glob`].
+///
+/// Follows every position in the pattern `text` could have reached at once instead of
+/// backtracking, so it takes O(pattern × text) even for client-controlled text.
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    enum Token {
+        /// `**`, anything
+        Any,
+        /// `*`, anything but `/`
+        Segment,
+        /// `?`, a single character but `/`
+        One,
+        Literal(u8),
+    }
+
+    let mut tokens = Vec::new();
+    let mut pattern = pattern.as_bytes();
+    while let Some((&c, rest)) = pattern.split_first() {
+        let (token, rest) = match (c, rest) {
+            (b'*', [b'*', rest @ ..]) => (Token::Any, rest),
+            (b'*', _) => (Token::Segment, rest),
+            (b'?', _) => (Token::One, rest),
+            (c, _) => (Token::Literal(c), rest),
+        };
+        tokens.push(token);
+        pattern = rest;
+    }
+
+    // wildcards can match nothing, so the positions after them are reached as well
+    let close = |positions: &mut Vec<bool>| {
+        for i in 0..tokens.len() {
+            if positions[i] && matches!(tokens[i], Token::Any | Token::Segment) {
+                positions[i + 1] = true;
+            }
+        }
+    };
+
+    let mut positions = vec![false; tokens.len() + 1];
+    positions[0] = true;
+    close(&mut positions);
+    for &c in text.as_bytes() {
+        let mut next = vec![false; tokens.len() + 1];
+        for (i, token) in tokens.iter().enumerate().filter(|&(i, _)| positions[i]) {
+            match token {
+                Token::Any => next[i] = true,
+                Token::Segment if c != b'/' => next[i] = true,
+                Token::One if c != b'/' => next[i + 1] = true,
+                Token::Literal(literal) if *literal == c => next[i + 1] = true,
+                _ => {}
+            }
+        }
+        close(&mut next);
+        if !next.contains(&true) {
+            return false;
+        }
+        positions = next;
+    }
+
+    positions[tokens.len()]
+}
+
+#[test]
+fn test_glob_match() {
+    for (pattern, text) in [
+        ("*.js", "app.js"),
+        ("*.js", ".js"),
+        ("app.??", "app.js"),
+        ("/assets/*", "/assets/app.js"),
+        ("/assets/**", "/assets/js/app.js"),
+        ("/**/*.css", "/a/b/c.css"),
+        ("/**.css", "/c.css"),
+    ] {
+        assert!(glob_match(pattern, text), "{pattern} {text}");
+    }
+
+    for (pattern, text) in [
+        ("*.js", "app.jsx"),
+        ("/assets/*", "/assets/js/app.js"),
+        ("/assets/?", "/assets//"),
+        ("app.?", "app.js"),
+        ("/**/*.css", "/a/b/c.cs"),
+    ] {
+        assert!(!glob_match(pattern, text), "{pattern} {text}");
+    }
+
+    // would take ages with backtracking
+    let text = "a".repeat(64 * 1024);
+    assert!(!glob_match("*a*a*a*a*b", &text));
+    assert!(glob_match("**a**a**a**", &text));
+}
@@ -51,12 +51,40 @@ impl<T: ToString> From<T> for HeaderKey {
 }
 
 /// Header key-value map
-pub struct Headers(HashMap<HeaderKey, String>);
+///
+/// A header usually has a single value, [`Headers::append`] adds more that are sent as
+/// separate lines, like `Set-Cookie` needs.
+pub struct Headers(HashMap<HeaderKey, Vec<String>>);
 
 impl Headers {
     /// Get a header's value, `key` is case insensitive
     pub fn get(&self, key: impl Into<HeaderKey>) -> Option<&String> {
-        self.0.get(&key.into())
+        self.0.get(&key.into()).and_then(|values| values.first())
+    }
+
+    /// Get every value of a header, `key` is case insensitive
+    pub fn get_all(&self, key: impl Into<HeaderKey>) -> &[String] {
+        self.0.get(&key.into()).map_or(&[], Vec::as_slice)
+    }
+
+    /// Set `key` to `value`, replacing any previous value
+    pub fn insert(&mut self, key: impl Into<HeaderKey>, value: impl ToString) {
+        self.0.insert(key.into(), vec![value.to_string()]);
+    }
+
+    /// Add another `value` for `key`, keeping the previous ones
+    pub fn append(&mut self, key: impl Into<HeaderKey>, value: impl ToString) {
+        self.0
+            .entry(key.into())
+            .or_default()
+            .push(value.to_string());
+    }
+
+    /// Remove `key`, returning its first value if it was set
+    pub fn remove(&mut self, key: impl Into<HeaderKey>) -> Option<String> {
+        self.0
+            .remove(&key.into())
+            .and_then(|values| values.into_iter().next())
     }
 
     /// Get the [`HeadersBuilder`]
@@ -70,7 +98,7 @@ impl<K: ToString, V: ToString> From<HashMap<K, V>> for Headers {
         Self(
             value
                 .iter()
-                .map(|(k, v)| (k.to_string().into(), v.to_string()))
+                .map(|(k, v)| (k.to_string().into(), vec![v.to_string()]))
                 .collect(),
         )
     }
@@ -78,9 +106,11 @@ impl<K: ToString, V: ToString> From<HashMap<K, V>> for Headers {
 
 impl Display for Headers {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0
-            .iter()
-            .try_for_each(|(k, v)| writeln!(f, "{}: {}", **k, v))
+        self.0.iter().try_for_each(|(k, values)| {
+            values
+                .iter()
+                .try_for_each(|v| write!(f, "{}: {}\r\n", **k, v))
+        })
     }
 }
 
@@ -99,7 +129,7 @@ impl HeadersBuilder {
 
     /// Construct [`Headers`]
     pub fn build(self) -> Headers {
-        Headers(self.0)
+        Headers(self.0.into_iter().map(|(k, v)| (k, vec![v])).collect())
     }
 }
 
@@ -147,6 +177,7 @@ impl FromStr for Method {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         Ok(match s {
             "GET" => Self::Get,
+            "HEAD" => Self::Head,
             "POST" => Self::Post,
             "PUT" => Self::Put,
             "DELETE" => Self::Delete,
@@ -159,6 +190,187 @@ impl FromStr for Method {
     }
 }
 
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+/// Supported HTTP versions
+pub enum Version {
+    /// HTTP/1.0, connections are closed after every response unless asked otherwise
+    Http10,
+    /// HTTP/1.1
+    Http11,
+}
+
+impl FromStr for Version {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "HTTP/1.0" => Ok(Self::Http10),
+            "HTTP/1.1" => Ok(Self::Http11),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Http10 => "HTTP/1.0",
+            Self::Http11 => "HTTP/1.1",
+        })
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+/// Parsed request target
+///
+/// Covers all four forms a request target can take:
+/// - origin-form: `/path?query`
+/// - absolute-form: `http://example.com/path?query`
+/// - authority-form (`CONNECT` only): `example.com:443`
+/// - asterisk-form (`OPTIONS` only): `*`
+///
+/// # Examples
+/// ```
+/// # use rust_http_server::common::Uri;
+/// let uri: Uri = "http://example.com:8080/a?b=c".parse().unwrap();
+/// assert_eq!(uri.scheme.as_deref(), Some("http"));
+/// assert_eq!(uri.authority.as_deref(), Some("example.com:8080"));
+/// assert_eq!(uri.path, "/a");
+/// assert_eq!(uri.query.as_deref(), Some("b=c"));
+/// ```
+pub struct Uri {
+    /// Scheme, only present in absolute-form
+    pub scheme: Option<String>,
+    /// `host[:port]`, present in absolute-form and authority-form
+    pub authority: Option<String>,
+    /// Path, `*` in asterisk-form and empty in authority-form
+    pub path: String,
+    /// Everything after the `?`, without it
+    pub query: Option<String>,
+}
+
+impl Uri {
+    /// Whether this is an asterisk-form target (`*`)
+    pub fn is_asterisk(&self) -> bool {
+        self.path == "*" && self.authority.is_none()
+    }
+
+    /// Whether this is an authority-form target (`host:port`)
+    pub fn is_authority(&self) -> bool {
+        self.scheme.is_none() && self.authority.is_some()
+    }
+
+    /// Check that `authority` is a plausible `host[:port]`, without userinfo
+    pub fn is_valid_authority(authority: &str) -> bool {
+        let (host, port) = match authority.strip_prefix('[') {
+            // IP literal
+            Some(rest) => match rest.split_once(']') {
+                Some((host, rest)) => (
+                    host,
+                    match rest {
+                        "" => None,
+                        rest => match rest.strip_prefix(':') {
+                            Some(port) => Some(port),
+                            None => return false,
+                        },
+                    },
+                ),
+                None => return false,
+            },
+            None => match authority.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+
+        !host.is_empty()
+            && host
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"-._~!$&'()*+,;=:%".contains(&b))
+            && port.is_none_or(|port| port.bytes().all(|b| b.is_ascii_digit()))
+    }
+}
+
+impl FromStr for Uri {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "*" {
+            return Ok(Self {
+                path: s.to_string(),
+                ..Default::default()
+            });
+        }
+
+        let split_query = |target: &str| match target.split_once('?') {
+            Some((path, query)) => (path.to_string(), Some(query.to_string())),
+            None => (target.to_string(), None),
+        };
+
+        if s.starts_with('/') {
+            let (path, query) = split_query(s);
+            return Ok(Self {
+                path,
+                query,
+                ..Default::default()
+            });
+        }
+
+        if let Some((scheme, rest)) = s.split_once("://") {
+            let valid_scheme = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"+-.".contains(&b));
+            if !valid_scheme {
+                return Err(());
+            }
+
+            let end = rest.find(['/', '?']).unwrap_or(rest.len());
+            let (authority, rest) = rest.split_at(end);
+            if !Self::is_valid_authority(authority) {
+                return Err(());
+            }
+
+            let (path, query) = split_query(rest);
+            return Ok(Self {
+                scheme: Some(scheme.to_lowercase()),
+                authority: Some(authority.to_string()),
+                path: if path.is_empty() {
+                    "/".to_string()
+                } else {
+                    path
+                },
+                query,
+            });
+        }
+
+        if Self::is_valid_authority(s) && s.contains(':') {
+            return Ok(Self {
+                authority: Some(s.to_string()),
+                ..Default::default()
+            });
+        }
+
+        Err(())
+    }
+}
+
+impl Display for Uri {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(scheme) = &self.scheme {
+            write!(f, "{scheme}://")?;
+        }
+        if let Some(authority) = &self.authority {
+            f.write_str(authority)?;
+        }
+        f.write_str(&self.path)?;
+        if let Some(query) = &self.query {
+            write!(f, "?{query}")?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Default)]
 /// URL Search Params
 pub struct Search(HashMap<String, String>);
@@ -185,5 +397,239 @@ impl Deref for Search {
     }
 }
 
+/// Quality value (`q=`) given to `value` in a header like `Accept-Encoding: gzip;q=0.8, br`
+///
+/// Values are compared case-insensitively, `*` applies to anything not listed explicitly.
+/// Returns `0.0` if `value` is not accepted at all.
+///
+/// # Examples
+/// ```
+/// # use rust_http_server::common::quality;
+/// let header = "gzip;q=0.8, br, *;q=0.1";
+/// assert_eq!(quality(header, "br"), 1.0);
+/// assert_eq!(quality(header, "GZIP"), 0.8);
+/// assert_eq!(quality(header, "deflate"), 0.1);
+/// assert_eq!(quality("gzip", "br"), 0.0);
+/// ```
+pub fn quality(header: &str, value: &str) -> f32 {
+    let mut wildcard = None;
+
+    for item in header.split(',') {
+        let mut params = item.split(';').map(str::trim);
+        let name = params.next().unwrap_or_default();
+        let q = params
+            .find_map(|param| {
+                let (key, value) = param.split_once('=')?;
+                key.trim()
+                    .eq_ignore_ascii_case("q")
+                    .then(|| value.trim().parse::<f32>().ok())?
+            })
+            .unwrap_or(1.0)
+            .clamp(0.0, 1.0);
+
+        if name.eq_ignore_ascii_case(value) {
+            return q;
+        } else if name == "*" {
+            wildcard = Some(q);
+        }
+    }
+
+    wildcard.unwrap_or(0.0)
+}
+
+/// Quality an `Accept` header gives `media_type`, using the most specific matching range.
+///
+/// A missing header accepts everything.
+///
+/// # Examples
+/// ```
+/// # use rust_http_server::common::media_quality;
+/// let accept = "text/html, text/*;q=0.5, */*;q=0.1";
+/// assert_eq!(media_quality(Some(accept), "text/html"), 1.0);
+/// assert_eq!(media_quality(Some(accept), "text/plain"), 0.5);
+/// assert_eq!(media_quality(Some(accept), "image/png"), 0.1);
+/// assert_eq!(media_quality(Some("application/json"), "text/html"), 0.0);
+/// assert_eq!(media_quality(None, "text/html"), 1.0);
+/// ```
+pub fn media_quality(accept: Option<&str>, media_type: &str) -> f32 {
+    let Some(accept) = accept else {
+        return 1.0;
+    };
+    let listed = |range: &str| {
+        accept.split(',').any(|item| {
+            item.split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .eq_ignore_ascii_case(range)
+        })
+    };
+
+    let subtype_range = format!("{}/*", media_type.split('/').next().unwrap_or_default());
+    let range = [media_type, &subtype_range, "*/*"]
+        .into_iter()
+        .find(|range| listed(range));
+    range.map_or(0.0, |range| quality(accept, range))
+}
+
+/// Decode `%XX` escapes, [`None`] if an escape is malformed
+///
+/// # Examples
+/// ```
+/// # use rust_http_server::common::percent_decode;
+/// assert_eq!(percent_decode("a%20b"), Some(b"a b".to_vec()));
+/// assert_eq!(percent_decode("100%"), None);
+/// ```
+pub fn percent_decode(s: &str) -> Option<Vec<u8>> {
+    let mut bytes = s.bytes();
+    let mut out = Vec::with_capacity(s.len());
+
+    while let Some(byte) = bytes.next() {
+        if byte == b'%' {
+            let hex = [bytes.next()?, bytes.next()?];
+            out.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            out.push(byte);
+        }
+    }
+
+    Some(out)
+}
+
+/// Percent-encode everything in `s` except unreserved characters, so it can be used as a path segment
+///
+/// # Examples
+/// ```
+/// # use rust_http_server::common::percent_encode;
+/// assert_eq!(percent_encode("a b/c?.txt"), "a%20b%2Fc%3F.txt");
+/// ```
+pub fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .fold(String::with_capacity(s.len()), |mut out, byte| {
+            match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                    out.push(char::from(byte))
+                }
+                _ => out += &format!("%{byte:02X}"),
+            }
+            out
+        })
+}
+
+/// Escape `s` for use in HTML text and attribute values
+///
+/// # Examples
+/// ```
+/// # use rust_http_server::common::html_escape;
+/// assert_eq!(html_escape("<a href=\"x\">&'"), "&lt;a href=&quot;x&quot;&gt;&amp;&#39;");
+/// ```
+pub fn html_escape(s: &str) -> String {
+    s.chars()
+        .fold(String::with_capacity(s.len()), |mut out, c| {
+            match c {
+                '&' => out += "&amp;",
+                '<' => out += "&lt;",
+                '>' => out += "&gt;",
+                '"' => out += "&quot;",
+                '\'' => out += "&#39;",
+                c => out.push(c),
+            }
+            out
+        })
+}
+
+/// Decode standard base64 (RFC 4648), the padding is optional
+///
+/// # Examples
+/// ```
+/// # use rust_http_server::common::base64_decode;
+/// assert_eq!(base64_decode("dXNlcjpwYXNz"), Some(b"user:pass".to_vec()));
+/// assert_eq!(base64_decode("YQ=="), Some(b"a".to_vec()));
+/// assert_eq!(base64_decode("YQ"), Some(b"a".to_vec()));
+/// assert_eq!(base64_decode("Y"), None);
+/// assert_eq!(base64_decode("Y Q="), None);
+/// ```
+pub fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    let s = s.as_bytes();
+    let data = match s {
+        [data @ .., b'=', b'='] | [data @ .., b'='] if s.len().is_multiple_of(4) => data,
+        data => data,
+    };
+    if data.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut out = Vec::with_capacity(data.len() * 3 / 4);
+    let (mut bits, mut count) = (0u32, 0);
+    for &c in data {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        bits = bits << 6 | u32::from(value);
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+            bits &= (1 << count) - 1;
+        }
+    }
+
+    Some(out)
+}
+
+/// Add `name` to the `Vary` header of a response unless it is listed already
+pub(crate) fn add_vary(headers: &mut Headers, name: &str) {
+    let vary = match headers.get("Vary") {
+        Some(vary)
+            if vary
+                .split(',')
+                .any(|v| v.trim().eq_ignore_ascii_case(name) || v.trim() == "*") =>
+        {
+            return
+        }
+        Some(vary) => format!("{vary}, {name}"),
+        None => name.to_string(),
+    };
+    headers.insert("Vary", vary);
+}
+
 #[deprecated = "use ::handlers::Handler instead"]
 pub use crate::handlers::Handler;
+
+#[test]
+fn test_uri_forms() {
+    let uri: Uri = "/a/b?c=d".parse().unwrap();
+    assert_eq!((uri.scheme, uri.authority), (None, None));
+    assert_eq!(
+        (uri.path.as_str(), uri.query.as_deref()),
+        ("/a/b", Some("c=d"))
+    );
+
+    let uri: Uri = "HTTP://example.com".parse().unwrap();
+    assert_eq!(uri.scheme.as_deref(), Some("http"));
+    assert_eq!(uri.authority.as_deref(), Some("example.com"));
+    assert_eq!(uri.path, "/");
+    assert_eq!(uri.to_string(), "http://example.com/");
+
+    let uri: Uri = "[::1]:443".parse().unwrap();
+    assert!(uri.is_authority());
+    assert_eq!(uri.authority.as_deref(), Some("[::1]:443"));
+
+    assert!("*".parse::<Uri>().unwrap().is_asterisk());
+
+    for invalid in [
+        "",
+        "a/b",
+        "http://user@host/",
+        "http:///a",
+        "1ttp://a/",
+        "host:port",
+    ] {
+        assert!(invalid.parse::<Uri>().is_err(), "{invalid:?}");
+    }
+}
@@ -0,0 +1,1042 @@
+//! Response compression with a self-contained DEFLATE encoder (RFC 1950, 1951, 1952)
+
+use crate::{
+    common::{add_vary, quality},
+    handlers::Middleware,
+    request::{Body, Request},
+    response::Response,
+};
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    io::{Cursor, Read},
+};
+
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// How many earlier positions with the same hash are compared before giving up
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+/// Tokens per block, every block gets its own Huffman codes
+const BLOCK_TOKENS: usize = 16 * 1024;
+const MAX_STORED: usize = u16::MAX as usize;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order in which code length code lengths are sent
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 == 1 {
+                0xEDB8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+};
+
+/// CRC-32 as used by gzip and zip
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+/// Continue the CRC-32 `crc` of earlier data with `data`
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |crc, &byte| {
+        CRC_TABLE[((crc ^ u32::from(byte)) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Adler-32 as used by zlib
+pub fn adler32(data: &[u8]) -> u32 {
+    let (a, b) = data
+        .chunks(5552)
+        .fold((1u32, 0u32), |(mut a, mut b), chunk| {
+            for &byte in chunk {
+                a += u32::from(byte);
+                b += a;
+            }
+            (a % 65521, b % 65521)
+        });
+    (b << 16) | a
+}
+
+/// Writes bits least significant first, as DEFLATE wants them
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            out: Vec::new(),
+            bits: 0,
+            count: 0,
+        }
+    }
+
+    fn write(&mut self, value: u32, count: u32) {
+        self.bits |= u64::from(value) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Write a Huffman code, which is sent most significant bit first
+    fn write_code(&mut self, code: u16, length: u8) {
+        let reversed = code.reverse_bits() >> (16 - u32::from(length));
+        self.write(u32::from(reversed), u32::from(length));
+    }
+
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.write(0, 8 - self.count);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.align();
+        self.out
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Token {
+    Literal(u8),
+    Match { length: u16, distance: u16 },
+}
+
+/// Index into [`LENGTH_BASE`], the length symbol is this plus 257
+fn length_code(length: u16) -> usize {
+    LENGTH_BASE
+        .iter()
+        .rposition(|&base| base <= length)
+        .unwrap()
+}
+
+/// Index into [`DIST_BASE`]
+fn distance_code(distance: u16) -> usize {
+    DIST_BASE
+        .iter()
+        .rposition(|&base| base <= distance)
+        .unwrap()
+}
+
+/// Find repeated strings with hash chains, greedily taking the longest match
+fn lz77(data: &[u8]) -> Vec<Token> {
+    let hash = |i: usize| {
+        ((usize::from(data[i]) << 10) ^ (usize::from(data[i + 1]) << 5) ^ usize::from(data[i + 2]))
+            & ((1 << HASH_BITS) - 1)
+    };
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+    let insert = |i: usize, head: &mut Vec<usize>, prev: &mut Vec<usize>| {
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(i);
+            prev[i % WINDOW_SIZE] = head[h];
+            head[h] = i;
+        }
+    };
+
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let mut best = (0, 0);
+
+        if i + MIN_MATCH <= data.len() {
+            let max = MAX_MATCH.min(data.len() - i);
+            let mut candidate = head[hash(i)];
+            let mut chain = 0;
+
+            while candidate != usize::MAX && i - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let length = data[candidate..]
+                    .iter()
+                    .zip(&data[i..i + max])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if length > best.0 {
+                    best = (length, i - candidate);
+                    if length == max {
+                        break;
+                    }
+                }
+
+                let next = prev[candidate % WINDOW_SIZE];
+                if next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        if best.0 >= MIN_MATCH {
+            tokens.push(Token::Match {
+                length: best.0 as u16,
+                distance: best.1 as u16,
+            });
+            for j in i..i + best.0 {
+                insert(j, &mut head, &mut prev);
+            }
+            i += best.0;
+        } else {
+            tokens.push(Token::Literal(data[i]));
+            insert(i, &mut head, &mut prev);
+            i += 1;
+        }
+    }
+
+    tokens
+}
+
+/// Huffman code lengths for `frequencies`, none longer than `max_length`.
+///
+/// At least two symbols always get a code, as some decoders reject codes with a single symbol.
+fn code_lengths(frequencies: &[u32], max_length: u8) -> Vec<u8> {
+    let mut frequencies = frequencies.to_vec();
+    for symbol in 0..2 {
+        if frequencies.iter().filter(|&&f| f > 0).count() < 2 && frequencies[symbol] == 0 {
+            frequencies[symbol] = 1;
+        }
+    }
+
+    loop {
+        // nodes are `(left, right)` children, leaves are `None`
+        let mut nodes: Vec<Option<(usize, usize)>> = Vec::new();
+        let mut symbols = Vec::new();
+        let mut heap = BinaryHeap::new();
+        for (symbol, &frequency) in frequencies.iter().enumerate() {
+            if frequency > 0 {
+                heap.push(Reverse((u64::from(frequency), nodes.len())));
+                nodes.push(None);
+                symbols.push(symbol);
+            }
+        }
+
+        while heap.len() > 1 {
+            let Reverse((a_weight, a)) = heap.pop().unwrap();
+            let Reverse((b_weight, b)) = heap.pop().unwrap();
+            heap.push(Reverse((a_weight + b_weight, nodes.len())));
+            nodes.push(Some((a, b)));
+        }
+
+        let mut lengths = vec![0; frequencies.len()];
+        let mut stack = vec![(nodes.len() - 1, 0u8)];
+        let mut too_long = false;
+        while let Some((node, depth)) = stack.pop() {
+            match nodes[node] {
+                Some((a, b)) => {
+                    stack.push((a, depth + 1));
+                    stack.push((b, depth + 1));
+                }
+                None => {
+                    too_long |= depth > max_length;
+                    lengths[symbols[node]] = depth;
+                }
+            }
+        }
+
+        if !too_long {
+            return lengths;
+        }
+
+        // flatten the distribution until the tree is shallow enough
+        for frequency in frequencies.iter_mut().filter(|f| **f > 0) {
+            *frequency = (*frequency >> 1) | 1;
+        }
+    }
+}
+
+/// Canonical Huffman codes for `lengths` (RFC 1951 section 3.2.2)
+fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut count = [0u16; 16];
+    for &length in lengths.iter().filter(|&&length| length > 0) {
+        count[usize::from(length)] += 1;
+    }
+
+    let mut next = [0u16; 16];
+    let mut code = 0;
+    for bits in 1..16 {
+        code = (code + count[bits - 1]) << 1;
+        next[bits] = code;
+    }
+
+    lengths
+        .iter()
+        .map(|&length| {
+            if length == 0 {
+                return 0;
+            }
+            let code = next[usize::from(length)];
+            next[usize::from(length)] += 1;
+            code
+        })
+        .collect()
+}
+
+/// Code lengths of the fixed Huffman codes (RFC 1951 section 3.2.6)
+fn fixed_lengths() -> (Vec<u8>, Vec<u8>) {
+    let literals = (0..288)
+        .map(|symbol| match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        })
+        .collect();
+    (literals, vec![5; 32])
+}
+
+/// Run-length encode code lengths with the symbols 16, 17 and 18, as `(symbol, extra bits value)`
+fn encode_lengths(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut encoded = Vec::new();
+    let mut i = 0;
+
+    while i < lengths.len() {
+        let length = lengths[i];
+        let run = lengths[i..].iter().take_while(|&&l| l == length).count();
+
+        if length == 0 && run >= 11 {
+            let run = run.min(138);
+            encoded.push((18, (run - 11) as u8));
+            i += run;
+        } else if length == 0 && run >= 3 {
+            let run = run.min(10);
+            encoded.push((17, (run - 3) as u8));
+            i += run;
+        } else if length != 0 && run >= 4 {
+            // the first one is sent as is, 16 repeats the previous length
+            encoded.push((length, 0));
+            let run = (run - 1).min(6);
+            encoded.push((16, (run - 3) as u8));
+            i += 1 + run;
+        } else {
+            encoded.push((length, 0));
+            i += 1;
+        }
+    }
+
+    encoded
+}
+
+/// Huffman codes for one block, either fixed or built from the block itself
+struct BlockCodes {
+    literal_lengths: Vec<u8>,
+    literal_codes: Vec<u16>,
+    distance_lengths: Vec<u8>,
+    distance_codes: Vec<u16>,
+}
+
+impl BlockCodes {
+    fn new(literal_lengths: Vec<u8>, distance_lengths: Vec<u8>) -> Self {
+        Self {
+            literal_codes: canonical_codes(&literal_lengths),
+            literal_lengths,
+            distance_codes: canonical_codes(&distance_lengths),
+            distance_lengths,
+        }
+    }
+
+    /// Size of `tokens` and the end of block marker in bits
+    fn cost(&self, tokens: &[Token]) -> usize {
+        tokens
+            .iter()
+            .map(|token| match *token {
+                Token::Literal(byte) => usize::from(self.literal_lengths[usize::from(byte)]),
+                Token::Match { length, distance } => {
+                    let (l, d) = (length_code(length), distance_code(distance));
+                    usize::from(self.literal_lengths[257 + l])
+                        + usize::from(LENGTH_EXTRA[l])
+                        + usize::from(self.distance_lengths[d])
+                        + usize::from(DIST_EXTRA[d])
+                }
+            })
+            .sum::<usize>()
+            + usize::from(self.literal_lengths[256])
+    }
+
+    fn write(&self, writer: &mut BitWriter, tokens: &[Token]) {
+        for token in tokens {
+            match *token {
+                Token::Literal(byte) => writer.write_code(
+                    self.literal_codes[usize::from(byte)],
+                    self.literal_lengths[usize::from(byte)],
+                ),
+                Token::Match { length, distance } => {
+                    let l = length_code(length);
+                    writer.write_code(self.literal_codes[257 + l], self.literal_lengths[257 + l]);
+                    writer.write(
+                        u32::from(length - LENGTH_BASE[l]),
+                        u32::from(LENGTH_EXTRA[l]),
+                    );
+
+                    let d = distance_code(distance);
+                    writer.write_code(self.distance_codes[d], self.distance_lengths[d]);
+                    writer.write(u32::from(distance - DIST_BASE[d]), u32::from(DIST_EXTRA[d]));
+                }
+            }
+        }
+        writer.write_code(self.literal_codes[256], self.literal_lengths[256]);
+    }
+}
+
+/// Header of a block with dynamic Huffman codes
+struct DynamicHeader {
+    codes: BlockCodes,
+    literal_count: usize,
+    distance_count: usize,
+    encoded: Vec<(u8, u8)>,
+    code_length_lengths: Vec<u8>,
+    code_length_count: usize,
+}
+
+impl DynamicHeader {
+    fn new(tokens: &[Token]) -> Self {
+        let mut literal_frequencies = [0u32; 286];
+        let mut distance_frequencies = [0u32; 30];
+        literal_frequencies[256] = 1;
+        for token in tokens {
+            match *token {
+                Token::Literal(byte) => literal_frequencies[usize::from(byte)] += 1,
+                Token::Match { length, distance } => {
+                    literal_frequencies[257 + length_code(length)] += 1;
+                    distance_frequencies[distance_code(distance)] += 1;
+                }
+            }
+        }
+
+        let codes = BlockCodes::new(
+            code_lengths(&literal_frequencies, 15),
+            code_lengths(&distance_frequencies, 15),
+        );
+        let literal_count =
+            257.max(codes.literal_lengths.iter().rposition(|&l| l > 0).unwrap() + 1);
+        let distance_count =
+            1.max(codes.distance_lengths.iter().rposition(|&l| l > 0).unwrap() + 1);
+
+        let encoded = encode_lengths(
+            &[
+                &codes.literal_lengths[..literal_count],
+                &codes.distance_lengths[..distance_count],
+            ]
+            .concat(),
+        );
+
+        let mut code_length_frequencies = [0u32; 19];
+        for &(symbol, _) in &encoded {
+            code_length_frequencies[usize::from(symbol)] += 1;
+        }
+        let code_length_lengths = code_lengths(&code_length_frequencies, 7);
+        let code_length_count = 4.max(
+            CODE_LENGTH_ORDER
+                .iter()
+                .rposition(|&symbol| code_length_lengths[symbol] > 0)
+                .unwrap()
+                + 1,
+        );
+
+        Self {
+            codes,
+            literal_count,
+            distance_count,
+            encoded,
+            code_length_lengths,
+            code_length_count,
+        }
+    }
+
+    /// Size of the header in bits, without the 3 bits every block starts with
+    fn cost(&self) -> usize {
+        5 + 5
+            + 4
+            + 3 * self.code_length_count
+            + self
+                .encoded
+                .iter()
+                .map(|&(symbol, _)| {
+                    usize::from(self.code_length_lengths[usize::from(symbol)])
+                        + match symbol {
+                            16 => 2,
+                            17 => 3,
+                            18 => 7,
+                            _ => 0,
+                        }
+                })
+                .sum::<usize>()
+    }
+
+    fn write(&self, writer: &mut BitWriter) {
+        writer.write((self.literal_count - 257) as u32, 5);
+        writer.write((self.distance_count - 1) as u32, 5);
+        writer.write((self.code_length_count - 4) as u32, 4);
+        for &symbol in &CODE_LENGTH_ORDER[..self.code_length_count] {
+            writer.write(u32::from(self.code_length_lengths[symbol]), 3);
+        }
+
+        let codes = canonical_codes(&self.code_length_lengths);
+        for &(symbol, extra) in &self.encoded {
+            let symbol = usize::from(symbol);
+            writer.write_code(codes[symbol], self.code_length_lengths[symbol]);
+            match symbol {
+                16 => writer.write(u32::from(extra), 2),
+                17 => writer.write(u32::from(extra), 3),
+                18 => writer.write(u32::from(extra), 7),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Compress `data` into a raw DEFLATE stream.
+///
+/// Every block is sent stored, with fixed or with dynamic Huffman codes, whichever is smallest.
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let tokens = lz77(data);
+    let mut writer = BitWriter::new();
+    let fixed = {
+        let (literals, distances) = fixed_lengths();
+        BlockCodes::new(literals, distances)
+    };
+
+    let blocks = tokens.chunks(BLOCK_TOKENS).collect::<Vec<_>>();
+    let mut start = 0;
+    for (i, block) in blocks.iter().enumerate() {
+        let last = u32::from(i + 1 == blocks.len());
+        let end = start
+            + block
+                .iter()
+                .map(|token| match token {
+                    Token::Literal(..) => 1,
+                    Token::Match { length, .. } => usize::from(*length),
+                })
+                .sum::<usize>();
+        let raw = &data[start..end];
+        start = end;
+
+        let dynamic = DynamicHeader::new(block);
+        let dynamic_cost = dynamic.cost() + dynamic.codes.cost(block);
+        let fixed_cost = fixed.cost(block);
+        // every stored chunk has 3 header bits, up to 7 bits of padding and LEN/NLEN
+        let stored_cost = raw.len().div_ceil(MAX_STORED).max(1) * (3 + 7 + 32) + raw.len() * 8;
+
+        if stored_cost < fixed_cost.min(dynamic_cost) {
+            let chunks = raw.chunks(MAX_STORED).collect::<Vec<_>>();
+            for (j, chunk) in chunks.iter().enumerate() {
+                writer.write(last & u32::from(j + 1 == chunks.len()), 1);
+                writer.write(0b00, 2);
+                writer.align();
+                writer.write(chunk.len() as u32, 16);
+                writer.write(!chunk.len() as u32 & 0xFFFF, 16);
+                for &byte in *chunk {
+                    writer.write(u32::from(byte), 8);
+                }
+            }
+        } else if fixed_cost <= dynamic_cost {
+            writer.write(last, 1);
+            writer.write(0b01, 2);
+            fixed.write(&mut writer, block);
+        } else {
+            writer.write(last, 1);
+            writer.write(0b10, 2);
+            dynamic.write(&mut writer);
+            dynamic.codes.write(&mut writer, block);
+        }
+    }
+
+    if blocks.is_empty() {
+        // an empty final block with fixed codes
+        writer.write(1, 1);
+        writer.write(0b01, 2);
+        fixed.write(&mut writer, &[]);
+    }
+
+    writer.finish()
+}
+
+/// Compress `data` with gzip framing (`Content-Encoding: gzip`)
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    // magic, deflate, no flags, no modification time, no extra flags, unknown OS
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    out.extend(deflate(data));
+    out.extend(crc32(data).to_le_bytes());
+    out.extend((data.len() as u32).to_le_bytes());
+    out
+}
+
+/// Compress `data` with zlib framing (`Content-Encoding: deflate`)
+pub fn zlib(data: &[u8]) -> Vec<u8> {
+    // 32K window, deflate, default compression level, checksum of these two bytes
+    let mut out = vec![0x78, 0x9c];
+    out.extend(deflate(data));
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+/// Response compression settings, see [`Compression::apply`] and [`compression`]
+///
+/// # Examples
+/// ```
+/// # use rust_http_server::{compression::{compression, Compression}, server::Server};
+/// let middleware = compression(Compression::new().min_size(512));
+/// let server = Server::new().wrap(&*middleware);
+/// ```
+pub struct Compression {
+    min_size: usize,
+    max_size: usize,
+    skip: Vec<String>,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Compression {
+    /// Compress bodies between 256 bytes and 8 MiB, skipping types that are compressed already
+    pub fn new() -> Self {
+        Self {
+            min_size: 256,
+            max_size: 8 * 1024 * 1024,
+            skip: [
+                "image/*",
+                "audio/*",
+                "video/*",
+                "font/woff",
+                "font/woff2",
+                "application/gzip",
+                "application/zip",
+                "application/wasm",
+                "application/octet-stream",
+            ]
+            .map(str::to_string)
+            .to_vec(),
+        }
+    }
+
+    /// Bodies smaller than this are not worth compressing
+    pub fn min_size(mut self, bytes: usize) -> Self {
+        self.min_size = bytes;
+        self
+    }
+
+    /// Bodies larger than this are sent as they are, as they have to be compressed in memory
+    pub fn max_size(mut self, bytes: usize) -> Self {
+        self.max_size = bytes;
+        self
+    }
+
+    /// Never compress responses with this `Content-Type`, `type/*` matches a whole type.
+    ///
+    /// `image/svg+xml` is compressible, so it is allowed again with `.allow_type("image/svg+xml")`
+    /// after the default `image/*` rule.
+    pub fn skip_type(mut self, mime_type: impl ToString) -> Self {
+        self.skip.push(mime_type.to_string().to_lowercase());
+        self
+    }
+
+    /// Compress responses with this `Content-Type` even if a [`skip_type`](Self::skip_type) rule matches
+    pub fn allow_type(mut self, mime_type: impl ToString) -> Self {
+        self.skip
+            .push(format!("!{}", mime_type.to_string().to_lowercase()));
+        self
+    }
+
+    fn skips(&self, content_type: &str) -> bool {
+        let essence = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+        let matches = |rule: &str| match rule.strip_suffix("/*") {
+            Some(kind) => essence.split('/').next() == Some(kind),
+            None => essence == rule,
+        };
+
+        // later rules win
+        self.skip
+            .iter()
+            .rev()
+            .find_map(|rule| match rule.strip_prefix('!') {
+                Some(rule) => matches(rule).then_some(false),
+                None => matches(rule).then_some(true),
+            })
+            .unwrap_or(false)
+    }
+
+    /// Compress `response` with gzip or deflate if `req` accepts it and it is worth it.
+    ///
+    /// Sets `Content-Encoding` and `Vary: Accept-Encoding`, and weakens a strong `ETag`,
+    /// as the compressed body is no longer byte for byte the same. `HEAD` requests get the same
+    /// headers as `GET`, so their bodies are compressed too.
+    pub fn apply(&self, req: &Request, mut response: Response) -> Response {
+        let eligible = response.status.code == 200
+            && response.headers.get("Content-Encoding").is_none()
+            && !response
+                .headers
+                .get("Content-Type")
+                .is_some_and(|content_type| self.skips(content_type));
+        let size = match &response.body {
+            Body::Data(data) => Some(data.len()),
+            Body::Stream(..) => response
+                .headers
+                .get("Content-Length")
+                .and_then(|length| length.parse().ok()),
+            Body::Empty => None,
+        };
+        if !eligible || !size.is_some_and(|size| (self.min_size..=self.max_size).contains(&size)) {
+            return response;
+        }
+
+        add_vary(&mut response.headers, "Accept-Encoding");
+
+        let accept_encoding = req
+            .headers
+            .get("Accept-Encoding")
+            .map_or("", String::as_str);
+        let (gzip_quality, deflate_quality) = (
+            quality(accept_encoding, "gzip"),
+            quality(accept_encoding, "deflate"),
+        );
+        let encoding = match gzip_quality.max(deflate_quality) {
+            quality if quality <= 0.0 => return response,
+            quality if quality == gzip_quality => "gzip",
+            _ => "deflate",
+        };
+
+        let data = match std::mem::replace(&mut response.body, Body::Empty) {
+            Body::Data(data) => data.into_bytes(),
+            Body::Stream(mut stream) => {
+                let mut data = Vec::new();
+                if stream.read_to_end(&mut data).is_err() {
+                    return Response::builder().status(500).build();
+                }
+                data
+            }
+            Body::Empty => unreachable!(),
+        };
+
+        let compressed = match encoding {
+            "gzip" => gzip(&data),
+            _ => zlib(&data),
+        };
+        response.headers.insert("Content-Encoding", encoding);
+        response.headers.insert("Content-Length", compressed.len());
+        // ranges are only served from the uncompressed body
+        response.headers.remove("Accept-Ranges");
+        if let Some(etag) = response
+            .headers
+            .get("ETag")
+            .filter(|etag| !etag.starts_with("W/"))
+        {
+            let etag = format!("W/{etag}");
+            response.headers.insert("ETag", etag);
+        }
+        response.body = Body::Stream(Box::new(Cursor::new(compressed)));
+        response
+    }
+}
+
+/// Middleware compressing responses according to `options`, see [`Compression::apply`]
+pub fn compression(options: Compression) -> Box<Middleware> {
+    Box::new(move |req, next| {
+        let response = next(req);
+        options.apply(req, response)
+    })
+}
+
+/// Decompress a raw DEFLATE stream, only used to check the encoder
+#[cfg(test)]
+fn inflate(data: &[u8]) -> Option<Vec<u8>> {
+    struct Bits<'a> {
+        data: &'a [u8],
+        position: usize,
+    }
+
+    impl Bits<'_> {
+        fn bit(&mut self) -> Option<u32> {
+            let byte = self.data.get(self.position / 8)?;
+            let bit = (byte >> (self.position % 8)) & 1;
+            self.position += 1;
+            Some(u32::from(bit))
+        }
+
+        fn bits(&mut self, count: u32) -> Option<u32> {
+            (0..count).try_fold(0, |value, i| Some(value | (self.bit()? << i)))
+        }
+
+        /// Decode a symbol with the canonical code described by `lengths`
+        fn symbol(&mut self, lengths: &[u8]) -> Option<usize> {
+            let codes = canonical_codes(lengths);
+            let (mut code, mut length) = (0u16, 0u8);
+            loop {
+                code = (code << 1) | self.bit()? as u16;
+                length += 1;
+                if length > 15 {
+                    return None;
+                }
+                if let Some(symbol) = (0..lengths.len())
+                    .find(|&symbol| lengths[symbol] == length && codes[symbol] == code)
+                {
+                    return Some(symbol);
+                }
+            }
+        }
+    }
+
+    let mut bits = Bits { data, position: 0 };
+    let mut out = Vec::new();
+
+    loop {
+        let last = bits.bit()?;
+        let (literals, distances) = match bits.bits(2)? {
+            0b00 => {
+                bits.position = bits.position.div_ceil(8) * 8;
+                let length = bits.bits(16)?;
+                if bits.bits(16)? != !length & 0xFFFF {
+                    return None;
+                }
+                for _ in 0..length {
+                    out.push(bits.bits(8)? as u8);
+                }
+                if last == 1 {
+                    return Some(out);
+                }
+                continue;
+            }
+            0b01 => fixed_lengths(),
+            0b10 => {
+                let literal_count = bits.bits(5)? as usize + 257;
+                let distance_count = bits.bits(5)? as usize + 1;
+                let code_length_count = bits.bits(4)? as usize + 4;
+                let mut code_length_lengths = [0u8; 19];
+                for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+                    code_length_lengths[symbol] = bits.bits(3)? as u8;
+                }
+
+                let mut lengths = Vec::new();
+                while lengths.len() < literal_count + distance_count {
+                    match bits.symbol(&code_length_lengths)? {
+                        16 => {
+                            let previous = *lengths.last()?;
+                            lengths
+                                .extend(std::iter::repeat_n(previous, 3 + bits.bits(2)? as usize));
+                        }
+                        17 => lengths.extend(std::iter::repeat_n(0, 3 + bits.bits(3)? as usize)),
+                        18 => lengths.extend(std::iter::repeat_n(0, 11 + bits.bits(7)? as usize)),
+                        length => lengths.push(length as u8),
+                    }
+                }
+                let distances = lengths.split_off(literal_count);
+                (lengths, distances)
+            }
+            _ => return None,
+        };
+
+        loop {
+            match bits.symbol(&literals)? {
+                literal @ 0..=255 => out.push(literal as u8),
+                256 => break,
+                symbol => {
+                    let l = symbol - 257;
+                    let length = usize::from(*LENGTH_BASE.get(l)?)
+                        + bits.bits(u32::from(LENGTH_EXTRA[l]))? as usize;
+                    let d = bits.symbol(&distances)?;
+                    let distance = usize::from(*DIST_BASE.get(d)?)
+                        + bits.bits(u32::from(DIST_EXTRA[d]))? as usize;
+                    let start = out.len().checked_sub(distance)?;
+                    for i in 0..length {
+                        out.push(out[start + i]);
+                    }
+                }
+            }
+        }
+
+        if last == 1 {
+            return Some(out);
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_inputs() -> Vec<Vec<u8>> {
+    let mut random = Vec::new();
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    for _ in 0..100_000 {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        random.push(state as u8);
+    }
+
+    let text = include_str!("./handlers.rs").as_bytes().to_vec();
+
+    vec![
+        Vec::new(),
+        b"a".to_vec(),
+        b"ab".to_vec(),
+        b"abcabcabcabcabcabcabc".to_vec(),
+        vec![0; 100_000],
+        (0..=255).cycle().take(300_000).collect(),
+        random.clone(),
+        text.repeat(3),
+        [&text[..], &random[..], &text[..]].concat(),
+    ]
+}
+
+#[test]
+fn test_deflate_round_trip() {
+    for input in test_inputs() {
+        let compressed = deflate(&input);
+        assert_eq!(
+            inflate(&compressed).as_deref(),
+            Some(&input[..]),
+            "{}",
+            input.len()
+        );
+    }
+
+    // repetitive data actually gets smaller and random data barely grows
+    assert!(deflate(&[0; 100_000]).len() < 1_000);
+    assert!(deflate(&test_inputs()[6]).len() < 100_000 + 100);
+}
+
+#[test]
+fn test_framing() {
+    for input in test_inputs() {
+        let gzipped = gzip(&input);
+        assert_eq!(gzipped[..3], [0x1f, 0x8b, 8]);
+        let (body, trailer) = gzipped[10..].split_at(gzipped.len() - 18);
+        assert_eq!(inflate(body).as_deref(), Some(&input[..]));
+        assert_eq!(trailer[..4], crc32(&input).to_le_bytes());
+        assert_eq!(trailer[4..], (input.len() as u32).to_le_bytes());
+
+        let zlibbed = zlib(&input);
+        assert_eq!((u16::from(zlibbed[0]) << 8 | u16::from(zlibbed[1])) % 31, 0);
+        let (body, trailer) = zlibbed[2..].split_at(zlibbed.len() - 6);
+        assert_eq!(inflate(body).as_deref(), Some(&input[..]));
+        assert_eq!(trailer, adler32(&input).to_be_bytes());
+    }
+
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(crc32_update(crc32(b"1234"), b"56789"), 0xCBF4_3926);
+    assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+}
+
+#[test]
+fn test_compression_middleware() {
+    use crate::{server::Server, test_utils::*};
+
+    let text = "compressible ".repeat(100);
+    // handlers are 'static, each owns a copy of the text
+    let html = {
+        let text = text.clone();
+        move |_: &Request| {
+            Response::builder()
+                .header("Content-Type", "text/html")
+                .header("ETag", "\"tag\"")
+                .header("Accept-Ranges", "bytes")
+                .body(text.clone())
+                .build()
+        }
+    };
+    let png = {
+        let text = text.clone();
+        move |_: &Request| {
+            Response::builder()
+                .header("Content-Type", "image/png")
+                .body(text.clone())
+                .build()
+        }
+    };
+    let small = |_: &Request| Response::builder().body("small").build();
+    let middleware = compression(Compression::new());
+    let server = Server::new()
+        .get("/html", &html)
+        .get("/png", &png)
+        .get("/small", &small)
+        .wrap(&*middleware);
+
+    let get = |path: &str, accept: &str| {
+        exchange_bytes(
+            &server,
+            format!(
+                "GET {path} HTTP/1.1\r\nConnection: close\r\nAccept-Encoding: {accept}\r\n\r\n"
+            )
+            .as_bytes(),
+        )
+    };
+    let split = |response: &[u8]| {
+        let end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        (
+            String::from_utf8_lossy(&response[..end]).into_owned(),
+            response[end + 4..].to_vec(),
+        )
+    };
+
+    let (head, body) = split(&get("/html", "gzip, deflate"));
+    assert!(head.contains("Content-Encoding: gzip"));
+    assert!(head.contains("Vary: Accept-Encoding"));
+    assert!(head.contains("ETag: W/\"tag\""));
+    assert!(head.contains(&format!("Content-Length: {}", body.len())));
+    assert!(!head.contains("Accept-Ranges"));
+    assert_eq!(inflate(&body[10..body.len() - 8]).unwrap(), text.as_bytes());
+
+    // HEAD announces the same encoding and length as GET
+    let response = exchange(
+        &server,
+        b"HEAD /html HTTP/1.1\r\nConnection: close\r\nAccept-Encoding: gzip\r\n\r\n",
+    );
+    assert!(response.contains("Content-Encoding: gzip"));
+    assert!(response.contains(&format!("Content-Length: {}\r\n", body.len())));
+    assert!(response.ends_with("\r\n\r\n"));
+
+    let (head, body) = split(&get("/html", "gzip;q=0.5, deflate"));
+    assert!(head.contains("Content-Encoding: deflate"));
+    assert_eq!(inflate(&body[2..body.len() - 4]).unwrap(), text.as_bytes());
+
+    let (head, body) = split(&get("/html", "br"));
+    assert!(!head.contains("Content-Encoding"));
+    assert!(head.contains("Vary: Accept-Encoding"));
+    assert_eq!(body, text.as_bytes());
+
+    let (head, _) = split(&get("/png", "gzip"));
+    assert!(!head.contains("Content-Encoding"));
+
+    let (head, body) = split(&get("/small", "gzip"));
+    assert!(!head.contains("Content-Encoding"));
+    assert_eq!(body, b"small");
+}
@@ -0,0 +1,323 @@
+//! Cookies sent by clients and `Set-Cookie` headers
+
+use crate::date::format_http_date;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    time::{Duration, SystemTime},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// Whether a cookie is sent along with requests from other sites
+pub enum SameSite {
+    /// Only with requests from the same site
+    Strict,
+    /// Also when following a link from another site
+    Lax,
+    /// With every request, requires [`CookieBuilder::secure`]
+    None,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+/// Reason [`CookieBuilder::build`] rejected a cookie
+pub enum CookieError {
+    /// The name is empty or contains characters other than token characters
+    InvalidName,
+    /// The value contains whitespace, `"`, `,`, `;`, `\` or control characters
+    InvalidValue,
+    /// The path contains `;` or control characters
+    InvalidPath,
+    /// The domain contains `;`, whitespace or control characters
+    InvalidDomain,
+    /// `SameSite=None` without `Secure`, which browsers reject
+    InsecureSameSiteNone,
+    /// A `__Secure-` or `__Host-` cookie without the attributes its prefix requires
+    InvalidPrefix,
+}
+
+impl Display for CookieError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::InvalidName => "invalid cookie name",
+            Self::InvalidValue => "invalid cookie value",
+            Self::InvalidPath => "invalid cookie path",
+            Self::InvalidDomain => "invalid cookie domain",
+            Self::InsecureSameSiteNone => "SameSite=None requires Secure",
+            Self::InvalidPrefix => "cookie does not meet the requirements of its prefix",
+        })
+    }
+}
+
+impl Error for CookieError {}
+
+#[derive(Clone, Debug)]
+/// A cookie to set with [`ResponseBuilder::cookie`](crate::response::ResponseBuilder::cookie),
+/// its [`Display`] is the value of the `Set-Cookie` header
+///
+/// # Examples
+/// ```
+/// # use rust_http_server::cookie::{Cookie, SameSite};
+/// # use std::time::Duration;
+/// let cookie = Cookie::builder("theme", "dark")
+///     .path("/")
+///     .max_age(Duration::from_secs(3600))
+///     .http_only()
+///     .same_site(SameSite::Lax)
+///     .build()
+///     .unwrap();
+///
+/// assert_eq!(cookie.to_string(), "theme=dark; Path=/; Max-Age=3600; HttpOnly; SameSite=Lax");
+/// assert!(Cookie::builder("theme", "dark mode").build().is_err());
+/// ```
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    expires: Option<SystemTime>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    /// Get the [`CookieBuilder`] for a cookie called `name` with `value`
+    pub fn builder(name: impl ToString, value: impl ToString) -> CookieBuilder {
+        CookieBuilder(Self {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        })
+    }
+
+    /// Name of the cookie
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Value of the cookie
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+impl Display for Cookie {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={path}")?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={domain}")?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", format_http_date(expires))?;
+        }
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => f.write_str("; SameSite=Strict"),
+            Some(SameSite::Lax) => f.write_str("; SameSite=Lax"),
+            Some(SameSite::None) => f.write_str("; SameSite=None"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Builder for [`Cookie`]
+pub struct CookieBuilder(Cookie);
+
+impl CookieBuilder {
+    /// Only send the cookie for paths below `path`
+    pub fn path(mut self, path: impl ToString) -> Self {
+        self.0.path = Some(path.to_string());
+        self
+    }
+
+    /// Also send the cookie to subdomains of `domain`
+    pub fn domain(mut self, domain: impl ToString) -> Self {
+        self.0.domain = Some(domain.to_string());
+        self
+    }
+
+    /// Remove the cookie after `max_age`, [`Duration::ZERO`] removes it right away
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.0.max_age = Some(max_age);
+        self
+    }
+
+    /// Remove the cookie at `expires`, [`CookieBuilder::max_age`] takes precedence
+    pub fn expires(mut self, expires: SystemTime) -> Self {
+        self.0.expires = Some(expires);
+        self
+    }
+
+    /// Only send the cookie over HTTPS
+    pub fn secure(mut self) -> Self {
+        self.0.secure = true;
+        self
+    }
+
+    /// Hide the cookie from scripts
+    pub fn http_only(mut self) -> Self {
+        self.0.http_only = true;
+        self
+    }
+
+    /// Set when the cookie is sent along with requests from other sites
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.0.same_site = Some(same_site);
+        self
+    }
+
+    /// Construct the [`Cookie`] after checking its name, value and attributes (RFC 6265)
+    pub fn build(self) -> Result<Cookie, CookieError> {
+        let cookie = self.0;
+
+        if cookie.name.is_empty() || !cookie.name.bytes().all(is_token) {
+            return Err(CookieError::InvalidName);
+        }
+
+        let value = match cookie.value.strip_prefix('"') {
+            Some(quoted) => quoted.strip_suffix('"').ok_or(CookieError::InvalidValue)?,
+            None => &cookie.value,
+        };
+        if !value.bytes().all(is_cookie_octet) {
+            return Err(CookieError::InvalidValue);
+        }
+
+        let attribute = |value: &str, extra: &[u8]| {
+            value
+                .bytes()
+                .all(|b| (0x20..0x7f).contains(&b) && b != b';' && !extra.contains(&b))
+        };
+        if !cookie
+            .path
+            .as_deref()
+            .is_none_or(|path| attribute(path, b""))
+        {
+            return Err(CookieError::InvalidPath);
+        }
+        if !cookie
+            .domain
+            .as_deref()
+            .is_none_or(|domain| !domain.is_empty() && attribute(domain, b" ,"))
+        {
+            return Err(CookieError::InvalidDomain);
+        }
+
+        if cookie.same_site == Some(SameSite::None) && !cookie.secure {
+            return Err(CookieError::InsecureSameSiteNone);
+        }
+        let prefixed = cookie.name.starts_with("__Secure-") || cookie.name.starts_with("__Host-");
+        let host_only = cookie.path.as_deref() == Some("/") && cookie.domain.is_none();
+        if prefixed && !cookie.secure || cookie.name.starts_with("__Host-") && !host_only {
+            return Err(CookieError::InvalidPrefix);
+        }
+
+        Ok(cookie)
+    }
+}
+
+/// Characters allowed in a token (RFC 9110 section 5.6.2)
+fn is_token(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+/// Characters allowed in a cookie value (RFC 6265 section 4.1.1)
+fn is_cookie_octet(b: u8) -> bool {
+    matches!(b, 0x21 | 0x23..=0x2b | 0x2d..=0x3a | 0x3c..=0x5b | 0x5d..=0x7e)
+}
+
+/// Parse the value of a `Cookie` header, see [`Request::cookies`](crate::request::Request::cookies)
+pub(crate) fn parse_cookies(header: &str) -> HashMap<String, String> {
+    let mut cookies = HashMap::new();
+
+    for pair in header.split(';') {
+        let (name, value) = match pair.split_once('=') {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None => continue,
+        };
+        if name.is_empty() || !name.bytes().all(is_token) {
+            continue;
+        }
+        let value = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(value);
+
+        // clients send cookies with more specific paths first
+        cookies
+            .entry(name.to_string())
+            .or_insert_with(|| value.to_string());
+    }
+
+    cookies
+}
+
+#[test]
+fn test_cookies() {
+    let cookies = parse_cookies("a=1; b=\"two\";c=; a=shadowed; invalid; bad name=x; d=e=f");
+    assert_eq!(cookies.len(), 4);
+    assert_eq!(cookies["a"], "1");
+    assert_eq!(cookies["b"], "two");
+    assert_eq!(cookies["c"], "");
+    assert_eq!(cookies["d"], "e=f");
+
+    let cookie = Cookie::builder("__Host-id", "\"abc\"")
+        .path("/")
+        .expires(SystemTime::UNIX_EPOCH)
+        .secure()
+        .same_site(SameSite::None)
+        .build()
+        .unwrap();
+    assert_eq!(
+        cookie.to_string(),
+        "__Host-id=\"abc\"; Path=/; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Secure; SameSite=None"
+    );
+
+    for (builder, err) in [
+        (Cookie::builder("", "x"), CookieError::InvalidName),
+        (Cookie::builder("a;b", "x"), CookieError::InvalidName),
+        (Cookie::builder("a", "x;y"), CookieError::InvalidValue),
+        (Cookie::builder("a", "\"x"), CookieError::InvalidValue),
+        (Cookie::builder("a", "é"), CookieError::InvalidValue),
+        (
+            Cookie::builder("a", "x").path("/; Secure"),
+            CookieError::InvalidPath,
+        ),
+        (
+            Cookie::builder("a", "x").domain("a b"),
+            CookieError::InvalidDomain,
+        ),
+        (
+            Cookie::builder("a", "x").same_site(SameSite::None),
+            CookieError::InsecureSameSiteNone,
+        ),
+        (
+            Cookie::builder("__Secure-a", "x"),
+            CookieError::InvalidPrefix,
+        ),
+        (
+            Cookie::builder("__Host-a", "x").secure().path("/app"),
+            CookieError::InvalidPrefix,
+        ),
+    ] {
+        assert_eq!(builder.build().unwrap_err(), err);
+    }
+}
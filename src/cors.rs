@@ -0,0 +1,290 @@
+//! Cross-origin resource sharing
+
+use crate::{
+    cache::glob_match,
+    common::{add_vary, Method},
+    handlers::Middleware,
+    request::Request,
+    response::Response,
+};
+use std::time::Duration;
+
+/// Which origins may use responses
+enum Origins {
+    /// Every origin
+    Any,
+    /// Origins matching one of these, `*` matches anything but `/`
+    List(Vec<String>),
+    /// Origins this returns `true` for
+    Predicate(Box<dyn Fn(&str) -> bool + Send + Sync>),
+}
+
+/// Options for [`cors`]
+///
+/// # Examples
+/// ```
+/// # use rust_http_server::{cors::{cors, Cors}, server::Server};
+/// # use std::time::Duration;
+/// let cors = cors(
+///     Cors::new()
+///         .origin("http://localhost:5173")
+///         .origin("https://*.example.com")
+///         .methods(["GET", "POST", "DELETE"])
+///         .credentials()
+///         .max_age(Duration::from_secs(600)),
+/// );
+/// let server = Server::new().wrap(&*cors);
+/// ```
+pub struct Cors {
+    origins: Origins,
+    methods: Vec<String>,
+    headers: Option<Vec<String>>,
+    expose_headers: Vec<String>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl Cors {
+    /// No origin is allowed until added, `GET`, `HEAD` and `POST` are allowed with any header
+    pub fn new() -> Self {
+        Self {
+            origins: Origins::List(Vec::new()),
+            methods: vec!["GET".to_string(), "HEAD".to_string(), "POST".to_string()],
+            headers: None,
+            expose_headers: Vec::new(),
+            credentials: false,
+            max_age: None,
+        }
+    }
+
+    /// Allow `origin`, e.g. `https://example.com`.
+    ///
+    /// `*` in it matches anything but `/`, so `https://*.example.com` allows all subdomains
+    /// and `*` allows every origin, which can not be combined with [`Cors::credentials`].
+    pub fn origin(mut self, origin: impl ToString) -> Self {
+        let origin = origin.to_string();
+        match &mut self.origins {
+            _ if origin == "*" => self.origins = Origins::Any,
+            Origins::Any => {}
+            Origins::List(origins) => origins.push(origin.to_lowercase()),
+            Origins::Predicate(..) => self.origins = Origins::List(vec![origin.to_lowercase()]),
+        }
+        self
+    }
+
+    /// Allow the origins `allow` returns `true` for, replacing all others
+    pub fn origin_fn(mut self, allow: impl Fn(&str) -> bool + Send + Sync + 'static) -> Self {
+        self.origins = Origins::Predicate(Box::new(allow));
+        self
+    }
+
+    /// Methods cross-origin requests may use
+    pub fn methods<T: ToString>(mut self, methods: impl IntoIterator<Item = T>) -> Self {
+        self.methods = methods.into_iter().map(|m| m.to_string()).collect();
+        self
+    }
+
+    /// Headers cross-origin requests may send, instead of any header
+    pub fn headers<T: ToString>(mut self, headers: impl IntoIterator<Item = T>) -> Self {
+        self.headers = Some(headers.into_iter().map(|h| h.to_string()).collect());
+        self
+    }
+
+    /// Response headers scripts may read besides the basic ones like `Content-Type`
+    pub fn expose_headers<T: ToString>(mut self, headers: impl IntoIterator<Item = T>) -> Self {
+        self.expose_headers = headers.into_iter().map(|h| h.to_string()).collect();
+        self
+    }
+
+    /// Allow requests with cookies or `Authorization`, only for origins that are listed
+    /// or allowed by [`Cors::origin_fn`]
+    pub fn credentials(mut self) -> Self {
+        self.credentials = true;
+        self
+    }
+
+    /// How long browsers may cache the answer to a preflight request
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    fn allows(&self, origin: &str) -> bool {
+        match &self.origins {
+            Origins::Any => true,
+            Origins::List(origins) => {
+                let origin = origin.to_lowercase();
+                origins.iter().any(|pattern| glob_match(pattern, &origin))
+            }
+            Origins::Predicate(allow) => allow(origin),
+        }
+    }
+
+    /// Answer preflight requests and add the CORS headers to responses for allowed origins
+    fn apply(&self, req: &mut Request, next: &dyn Fn(&mut Request) -> Response) -> Response {
+        let origin = match req.headers.get("Origin") {
+            Some(origin) => origin.clone(),
+            None => {
+                let mut response = next(req);
+                add_vary(&mut response.headers, "Origin");
+                return response;
+            }
+        };
+        let allowed = self.allows(&origin);
+        let preflight = req.method == Method::Options
+            && req.headers.get("Access-Control-Request-Method").is_some();
+
+        let mut response = match (preflight, allowed) {
+            (true, true) => {
+                let mut builder = Response::builder()
+                    .status(204)
+                    .header("Access-Control-Allow-Methods", self.methods.join(", "));
+                let headers = match &self.headers {
+                    Some(headers) => Some(headers.join(", ")),
+                    None => req.headers.get("Access-Control-Request-Headers").cloned(),
+                };
+                if let Some(headers) = headers {
+                    builder = builder.header("Access-Control-Allow-Headers", headers);
+                }
+                if let Some(max_age) = self.max_age {
+                    builder = builder.header("Access-Control-Max-Age", max_age.as_secs());
+                }
+                builder.build()
+            }
+            (true, false) => Response::builder().status(403).build(),
+            (false, _) => next(req),
+        };
+
+        if preflight {
+            add_vary(&mut response.headers, "Access-Control-Request-Method");
+            add_vary(&mut response.headers, "Access-Control-Request-Headers");
+        }
+        add_vary(&mut response.headers, "Origin");
+        if !allowed {
+            return response;
+        }
+
+        let headers = &mut response.headers;
+        match self.origins {
+            Origins::Any => headers.insert("Access-Control-Allow-Origin", "*"),
+            _ => headers.insert("Access-Control-Allow-Origin", origin),
+        }
+        if self.credentials {
+            headers.insert("Access-Control-Allow-Credentials", "true");
+        }
+        if !preflight && !self.expose_headers.is_empty() {
+            headers.insert(
+                "Access-Control-Expose-Headers",
+                self.expose_headers.join(", "),
+            );
+        }
+        response
+    }
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Middleware for cross-origin requests according to `options`.
+///
+/// Preflight `OPTIONS` requests are answered without calling the handlers, with `403 Forbidden`
+/// if the origin is not allowed.
+///
+/// # Panics
+/// If every origin is allowed together with [`Cors::credentials`], any site could then read
+/// responses meant for the user, e.g. files behind [`basic_auth`](crate::auth::basic_auth).
+pub fn cors(options: Cors) -> Box<Middleware> {
+    if options.credentials && matches!(options.origins, Origins::Any) {
+        panic!("CORS credentials can not be allowed for every origin, list the origins instead");
+    }
+
+    Box::new(move |req, next| options.apply(req, next))
+}
+
+#[test]
+fn test_cors() {
+    use crate::{server::Server, test_utils::exchange};
+
+    let handler = |_: &Request| {
+        Response::builder()
+            .header("Vary", "Accept")
+            .header("X-Total", "3")
+            .body("data")
+            .build()
+    };
+    let request = |server: &Server, method: &str, headers: &str| {
+        exchange(
+            server,
+            format!("{method} / HTTP/1.1\r\nConnection: close\r\n{headers}\r\n").as_bytes(),
+        )
+    };
+
+    let options = Cors::new()
+        .origin("http://localhost:5173")
+        .origin("https://*.example.com")
+        .methods(["GET", "PUT"])
+        .expose_headers(["X-Total"])
+        .credentials()
+        .max_age(Duration::from_secs(600));
+    let middleware = cors(options);
+    let server = Server::new().wrap(&*middleware).not_found(&handler);
+
+    let preflight = request(
+        &server,
+        "OPTIONS",
+        "Origin: https://app.example.com\r\nAccess-Control-Request-Method: PUT\r\n\
+         Access-Control-Request-Headers: content-type\r\n",
+    );
+    assert!(preflight.starts_with("HTTP/1.1 204"));
+    for header in [
+        "Access-Control-Allow-Origin: https://app.example.com\r\n",
+        "Access-Control-Allow-Methods: GET, PUT\r\n",
+        "Access-Control-Allow-Headers: content-type\r\n",
+        "Access-Control-Allow-Credentials: true\r\n",
+        "Access-Control-Max-Age: 600\r\n",
+        "Vary: Access-Control-Request-Method, Access-Control-Request-Headers, Origin\r\n",
+    ] {
+        assert!(preflight.contains(header), "{header}");
+    }
+
+    let response = request(&server, "GET", "Origin: http://localhost:5173\r\n");
+    assert!(response.ends_with("\r\n\r\ndata"));
+    assert!(response.contains("Access-Control-Allow-Origin: http://localhost:5173\r\n"));
+    assert!(response.contains("Access-Control-Expose-Headers: X-Total\r\n"));
+    assert!(response.contains("Vary: Accept, Origin\r\n"));
+
+    for origin in [
+        "https://evil.com",
+        "https://a.b/.example.com",
+        "http://localhost:5174",
+    ] {
+        let response = request(&server, "GET", &format!("Origin: {origin}\r\n"));
+        assert!(response.ends_with("\r\n\r\ndata"), "{origin}");
+        assert!(!response.contains("Access-Control"), "{origin}");
+
+        let preflight = request(
+            &server,
+            "OPTIONS",
+            &format!("Origin: {origin}\r\nAccess-Control-Request-Method: GET\r\n"),
+        );
+        assert!(preflight.starts_with("HTTP/1.1 403"), "{origin}");
+    }
+
+    let middleware = cors(Cors::new().origin("*"));
+    let server = Server::new().wrap(&*middleware).not_found(&handler);
+    let response = request(&server, "GET", "Origin: https://any.org\r\n");
+    assert!(response.contains("Access-Control-Allow-Origin: *\r\n"));
+    assert!(!response.contains("Access-Control-Allow-Credentials"));
+    // would echo every origin with credentials
+    assert!(std::panic::catch_unwind(|| cors(Cors::new().origin("*").credentials())).is_err());
+    assert!(std::panic::catch_unwind(|| cors(Cors::new().credentials().origin("*"))).is_err());
+
+    let middleware = cors(Cors::new().origin_fn(|origin| origin.ends_with(".test")));
+    let server = Server::new().wrap(&*middleware).not_found(&handler);
+    let response = request(&server, "GET", "Origin: http://a.test\r\n");
+    assert!(response.contains("Access-Control-Allow-Origin: http://a.test\r\n"));
+    assert!(request(&server, "GET", "").contains("Vary: Accept, Origin\r\n"));
+}
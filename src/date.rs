@@ -0,0 +1,173 @@
+//! HTTP dates (RFC 9110 section 5.6.7)
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// `(year, month, day)` of a day counted from 1970-01-01
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
+/// Days since 1970-01-01 of a `(year, month, day)`
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = i64::from((month + 9) % 12);
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146_097 + doe - 719_468
+}
+
+/// Seconds since the epoch, times before it are clamped to it
+pub fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Format `time` as an IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+///
+/// # Examples
+/// ```
+/// # use rust_http_server::date::format_http_date;
+/// # use std::time::{Duration, UNIX_EPOCH};
+/// let time = UNIX_EPOCH + Duration::from_secs(784111777);
+/// assert_eq!(format_http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+/// ```
+pub fn format_http_date(time: SystemTime) -> String {
+    let secs = unix_seconds(time) as i64;
+    let days = secs.div_euclid(86_400);
+    let secs = secs.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{weekday}, {day:02} {month} {year:04} {h:02}:{m:02}:{s:02} GMT",
+        // 1970-01-01 was a Thursday
+        weekday = DAYS[(days + 3).rem_euclid(7) as usize],
+        month = MONTHS[month as usize - 1],
+        h = secs / 3600,
+        m = secs / 60 % 60,
+        s = secs % 60,
+    )
+}
+
+/// Format `time` as an RFC 3339 timestamp in UTC, e.g. `1994-11-06T08:49:37Z`
+///
+/// # Examples
+/// ```
+/// # use rust_http_server::date::format_rfc3339;
+/// # use std::time::{Duration, UNIX_EPOCH};
+/// let time = UNIX_EPOCH + Duration::from_secs(784111777);
+/// assert_eq!(format_rfc3339(time), "1994-11-06T08:49:37Z");
+/// ```
+pub fn format_rfc3339(time: SystemTime) -> String {
+    let secs = unix_seconds(time) as i64;
+    let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+    let secs = secs.rem_euclid(86_400);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{h:02}:{m:02}:{s:02}Z",
+        h = secs / 3600,
+        m = secs / 60 % 60,
+        s = secs % 60,
+    )
+}
+
+/// Parse an HTTP date in any of the three formats recipients have to accept:
+/// IMF-fixdate, the obsolete RFC 850 format and asctime.
+///
+/// # Examples
+/// ```
+/// # use rust_http_server::date::parse_http_date;
+/// # use std::time::{Duration, UNIX_EPOCH};
+/// let time = Some(UNIX_EPOCH + Duration::from_secs(784111777));
+/// assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), time);
+/// assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), time);
+/// assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), time);
+/// ```
+pub fn parse_http_date(s: &str) -> Option<SystemTime> {
+    let month = |name: &str| MONTHS.iter().position(|m| *m == name).map(|m| m as u32 + 1);
+    let number = |s: &str| s.parse::<u32>().ok();
+
+    let (_, rest) = s.split_once([',', ' '])?;
+    let parts = rest.split_whitespace().collect::<Vec<_>>();
+
+    let (year, month, day, time) = match parts[..] {
+        // IMF-fixdate: Sun, 06 Nov 1994 08:49:37 GMT
+        [day, month_name, year, time, "GMT"] if s.contains(',') && year.len() == 4 => (
+            i64::from(number(year)?),
+            month(month_name)?,
+            number(day)?,
+            time,
+        ),
+        // RFC 850: Sunday, 06-Nov-94 08:49:37 GMT
+        [date, time, "GMT"] if s.contains(',') => {
+            let mut date = date.split('-');
+            let (day, month_name, year) = (date.next()?, date.next()?, date.next()?);
+            let year = i64::from(number(year)?);
+            // two digit years are taken to be between 1970 and 2069
+            let year = if year >= 70 { 1900 + year } else { 2000 + year };
+            (year, month(month_name)?, number(day)?, time)
+        }
+        // asctime: Sun Nov  6 08:49:37 1994
+        [month_name, day, time, year] => (
+            i64::from(number(year)?),
+            month(month_name)?,
+            number(day)?,
+            time,
+        ),
+        _ => return None,
+    };
+
+    let mut time = time.split(':').map(number);
+    let (h, m, sec) = (time.next()??, time.next()??, time.next()??);
+    if time.next().is_some() || !(1..=31).contains(&day) || h > 23 || m > 59 || sec > 60 {
+        return None;
+    }
+
+    let secs = days_from_civil(year, month, day) * 86_400
+        + i64::from(h) * 3600
+        + i64::from(m) * 60
+        + i64::from(sec);
+
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?))
+}
+
+#[test]
+fn test_http_dates() {
+    for secs in [0, 68_169_600, 951_782_400, 1_709_164_799, 4_107_542_400] {
+        let time = UNIX_EPOCH + Duration::from_secs(secs);
+        assert_eq!(parse_http_date(&format_http_date(time)), Some(time));
+    }
+
+    assert_eq!(
+        format_http_date(UNIX_EPOCH + Duration::from_secs(951_782_400)),
+        "Tue, 29 Feb 2000 00:00:00 GMT"
+    );
+
+    for invalid in [
+        "",
+        "Sun, 06 Nov 1994 08:49:37",
+        "Sun, 06 Nov 1994 25:49:37 GMT",
+        "Sun, 06 Foo 1994 08:49:37 GMT",
+        "06 Nov 1994 08:49:37 GMT",
+    ] {
+        assert_eq!(parse_http_date(invalid), None, "{invalid:?}");
+    }
+}
@@ -1,11 +1,24 @@
 //! Default handlers for [`Server`](crate::server::Server)
 
 use crate::{
+    archive::{download, Format},
+    cache::CacheRules,
+    common::{media_quality, percent_encode, quality, Method},
+    date::{format_http_date, parse_http_date, unix_seconds},
+    listing::listing,
     mime_types::MimeType,
+    path::{normalize, resolve, DotFiles, PathPolicy},
     request::{Body, Request},
-    response::Response,
+    response::{Response, ResponseBuilder, Status},
+    webdav::{is_write, upload, write},
+};
+use std::{
+    fs::File,
+    hash::{BuildHasher, RandomState},
+    io::{Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::SystemTime,
 };
-use std::path::Path;
 
 /// The way directories should be indexed
 pub enum IndexStyle {
@@ -17,87 +30,1034 @@ pub enum IndexStyle {
     ///
     /// Usually set to `index.html`
     IndexFile(String),
+    /// Return the first of these files (relative) that exists instead
+    ///
+    /// Usually set to `index.html` and `index.htm`
+    IndexFiles(Vec<String>),
 }
 
 /// structure of a handler
 pub type Handler = dyn Fn(&Request) -> Response;
 
+/// structure of a hook that sees a request before its body is received.
+///
+/// Returning a [`Response`] rejects the request without reading the body.
+pub type BodyHook = dyn Fn(&Request) -> Option<Response>;
+
+/// structure of a middleware, it wraps every handler of a [`Server`](crate::server::Server).
+///
+/// `next` runs the rest of the chain, a middleware can change the request before calling it,
+/// change the response it returns or answer without calling it at all.
+pub type Middleware = dyn Fn(&mut Request, &dyn Fn(&mut Request) -> Response) -> Response;
+
 /// Default implementation for a 404 page.
 pub fn not_found_handler_default(_: &Request) -> Response {
-    let body = Body::Data(include_str!("./default_pages/404.html").to_string());
+    error_page_default(404)
+}
+
+/// Built-in page for an error `status`, every error without a body of its own gets one
+pub fn error_page_default(status: u16) -> Response {
+    let status = Status::from(status);
+    let description = match status.code {
+        400 => "The request could not be understood.",
+        401 => "You have to sign in to see this.",
+        403 => "You are not allowed to see this.",
+        404 => "Could not find what you were looking for.",
+        405 => "This method is not allowed here.",
+        408 => "The request took too long.",
+        409 => "This conflicts with what is already there.",
+        413 => "The request is too large.",
+        414 => "The address is too long.",
+        416 => "The requested range is not available.",
+        code if code < 500 => "The request could not be completed.",
+        _ => "Something went wrong on our end.",
+    };
+    let title = match &status.message {
+        Some(message) => format!("{} - {message}", status.code),
+        None => status.code.to_string(),
+    };
 
     Response::builder()
-        .status(404)
+        .status(status)
         .header("Content-Type", "text/html")
-        .header("Content-Length", body.len())
-        .body(body)
+        .body(format!(
+            include_str!("./default_pages/error.html"),
+            status = title,
+            description = description,
+        ))
         .build()
 }
 
+/// Options for [`fs_handler_with`]
+///
+/// # Examples
+/// ```
+/// # use rust_http_server::{handlers::{fs_handler_with, FsOptions, IndexStyle}, path::DotFiles};
+/// let handler = fs_handler_with(
+///     "./public",
+///     FsOptions::new(IndexStyle::IndexDirectory).dotfiles(DotFiles::Deny),
+/// );
+/// ```
+pub struct FsOptions {
+    index_style: IndexStyle,
+    policy: PathPolicy,
+    weak_etags: bool,
+    precompressed: bool,
+    archives: bool,
+    spa_fallback: Option<String>,
+    cache: CacheRules,
+    writable: bool,
+}
+
+impl FsOptions {
+    /// Options with the default [`PathPolicy`]
+    pub fn new(index_style: IndexStyle) -> Self {
+        Self {
+            index_style,
+            policy: PathPolicy::new(),
+            weak_etags: false,
+            precompressed: true,
+            archives: false,
+            spa_fallback: None,
+            cache: CacheRules::new(),
+            writable: false,
+        }
+    }
+
+    /// Set how dotfiles are treated, see [`DotFiles`]
+    pub fn dotfiles(mut self, dotfiles: DotFiles) -> Self {
+        self.policy.dotfiles = dotfiles;
+        self
+    }
+
+    /// Allow symlinks that point outside of the served directory
+    pub fn external_symlinks(mut self, allow: bool) -> Self {
+        self.policy.external_symlinks = allow;
+        self
+    }
+
+    /// Mark the `ETag`s derived from file size and modification time as weak.
+    ///
+    /// Useful if files can change without their size or modification time changing.
+    pub fn weak_etags(mut self, weak: bool) -> Self {
+        self.weak_etags = weak;
+        self
+    }
+
+    /// Offer listed directories as archives with `?download=tar` or `?download=zip`.
+    ///
+    /// Only applies to [`IndexStyle::IndexDirectory`]. Disabled by default, as an archive of a
+    /// large tree takes a while to send, `?download` is then ignored.
+    pub fn archives(mut self, enabled: bool) -> Self {
+        self.archives = enabled;
+        self
+    }
+
+    /// Serve `file` (relative) for missing paths that look like client-side routes.
+    ///
+    /// Used for single-page applications: `GET /dashboard/settings` from a browser gets the
+    /// fallback, while missing paths with an extension like `/assets/app.js` still get a 404.
+    pub fn spa_fallback(mut self, file: impl ToString) -> Self {
+        self.spa_fallback = Some(file.to_string());
+        self
+    }
+
+    /// Set `Cache-Control` on files according to `rules`, see [`CacheRules`]
+    pub fn cache(mut self, rules: CacheRules) -> Self {
+        self.cache = rules;
+        self
+    }
+
+    /// Let clients change the directory: `PUT` stores a file, `DELETE` removes a file or
+    /// directory and `MKCOL` creates a directory.
+    ///
+    /// Directory listings also get a form that uploads files with a `multipart/form-data` `POST`,
+    /// which is refused for requests from other origins.
+    ///
+    /// Uploads are limited by [`Limits::max_body`](crate::limits::Limits::max_body) and written
+    /// to a temporary file that replaces the target once complete. Disabled by default, write
+    /// requests get `405 Method Not Allowed`.
+    pub fn writable(mut self, enabled: bool) -> Self {
+        self.writable = enabled;
+        self
+    }
+
+    /// Serve precompressed `.br`/`.gz` files next to the requested one to clients accepting them.
+    ///
+    /// Enabled by default.
+    pub fn precompressed(mut self, enabled: bool) -> Self {
+        self.precompressed = enabled;
+        self
+    }
+}
+
 /// Default handler for the filesystem
 pub fn fs_handler(directory: &str, index_style: IndexStyle) -> Box<Handler> {
+    fs_handler_with(directory, FsOptions::new(index_style))
+}
+
+/// Same as [`fs_handler`], with more [`FsOptions`]
+pub fn fs_handler_with(directory: &str, options: FsOptions) -> Box<Handler> {
     let directory = directory.to_string();
     Box::new(move |req| {
         let directory = directory.as_str();
 
-        assert!(req.pathname.starts_with('/'));
+        // asterisk-form and authority-form targets never name a file
+        if !req.pathname.starts_with('/') {
+            return Response::builder().status(404).build();
+        }
 
-        let path = Path::new(directory).join(String::from(".") + req.pathname.clone().as_str());
+        let path = match resolve(Path::new(directory), &req.pathname, &options.policy) {
+            Ok(path) => path,
+            Err(err) if err.status() == 404 => return not_found_handler_default(req),
+            Err(err) => return Response::builder().status(err.status()).build(),
+        };
 
-        if !path.exists() {
-            Response::builder().status(404).build()
-        } else if path.is_file() {
-            let mime_type = MimeType::get_for_path(&req.pathname);
-            match std::fs::read(path).map(String::from_utf8) {
-                Ok(Ok(file)) => Response::builder()
-                    .status(200)
-                    .header("Content-Type", mime_type)
-                    .body(file)
+        if is_write(req) {
+            return match options.writable {
+                true => write(Path::new(directory), &path, req),
+                false => Response::builder()
+                    .status(405)
+                    .header("Allow", "GET, HEAD")
                     .build(),
-                _ => Response::builder().status(500).build(),
-            }
+            };
+        }
+        // files sent with the upload form of the listing
+        if req.method == Method::Post && options.writable && path.is_dir() {
+            return upload(&path, req, &options.policy);
+        }
+
+        if path.is_file() {
+            serve_file(&path, directory, req, &options)
         } else if path.is_dir() {
-            match index_style {
-                IndexStyle::IndexDirectory => match std::fs::read_dir(path) {
-                    Ok(files) => {
-                        let files = files
-                            .filter_map(|file| Some(file.ok()?.file_name().to_str()?.to_string()))
-                            .fold(String::new(), |mut out, name| {
-                                out +=
-                                    format!(r#"<li><a href="./{name}">{name}</a></li>"#).as_str();
-                                out
-                            });
-
-                        Response::builder()
-                            .status(200)
-                            .header("Content-Type", "text/html")
-                            .body(format!(
-                                r#"<!DOCTYPE html>
-<html lang="en" dir="ltr">
-<head>
-    <title>Index of {pathname}</title>
-    <style>:root{{color-scheme:light dark;}}</style>
-</head>
-<body>
-    <h1>Index of {pathname}</h1>
-    <ul>{files}</ul>
-</body>
-</html>
-"#,
-                                pathname = req.pathname
-                            ))
-                            .build()
+            let index_files = match &options.index_style {
+                IndexStyle::NotFound => return not_found_handler_default(req),
+                IndexStyle::IndexDirectory => &[][..],
+                IndexStyle::IndexFile(filename) => std::slice::from_ref(filename),
+                IndexStyle::IndexFiles(filenames) => &filenames[..],
+            };
+
+            // relative links only work from inside the directory
+            if !req.pathname.ends_with('/') {
+                let path = normalize(&req.uri.path);
+                let path = path.trim_end_matches('/');
+                let location = match &req.uri.query {
+                    Some(query) => format!("{path}/?{query}"),
+                    None => format!("{path}/"),
+                };
+                return Response::builder()
+                    .status(301)
+                    .header("Location", location)
+                    .build();
+            }
+
+            match &options.index_style {
+                IndexStyle::IndexDirectory => {
+                    match req
+                        .search
+                        .get("download")
+                        .filter(|_| options.archives)
+                        .map(|name| Format::from_name(name))
+                    {
+                        Some(Some(format)) => {
+                            download(Path::new(directory), &path, req, format, &options.policy)
+                        }
+                        Some(None) => Response::builder().status(400).build(),
+                        None => listing(
+                            &path,
+                            req,
+                            &options.policy,
+                            options.archives,
+                            options.writable,
+                        ),
                     }
-                    Err(..) => Response::builder().status(500).build(),
-                },
-                IndexStyle::NotFound => Response::builder().status(404).build(),
-                IndexStyle::IndexFile(..) => {
-                    // TODO:
-                    Response::builder().status(500).build()
                 }
+                // index files are subject to the same policy as requested files
+                _ => match index_files
+                    .iter()
+                    .filter_map(|filename| {
+                        let filename: Vec<_> = filename.split('/').map(percent_encode).collect();
+                        let pathname = format!("{}{}", req.pathname, filename.join("/"));
+                        resolve(Path::new(directory), &pathname, &options.policy).ok()
+                    })
+                    .find(|path| path.is_file())
+                {
+                    Some(path) => serve_file(&path, directory, req, &options),
+                    None => not_found_handler_default(req),
+                },
             }
         } else {
-            unreachable!()
+            match &options.spa_fallback {
+                Some(fallback) if wants_spa_fallback(req) => {
+                    let fallback = Path::new(directory).join(fallback);
+                    if fallback.is_file() {
+                        serve_file(&fallback, directory, req, &options)
+                    } else {
+                        not_found_handler_default(req)
+                    }
+                }
+                _ => not_found_handler_default(req),
+            }
         }
     })
 }
+
+/// Whether a request for a missing file is a client-side route rather than a missing asset:
+/// a `GET` for a path without an extension from a client accepting HTML
+fn wants_spa_fallback(req: &Request) -> bool {
+    let last_segment = req.pathname.rsplit('/').next().unwrap_or_default();
+
+    matches!(req.method, Method::Get | Method::Head)
+        && !last_segment.contains('.')
+        && media_quality(req.headers.get("Accept").map(String::as_str), "text/html") > 0.0
+}
+
+/// Wrap `handler`, setting `Cache-Control` to `value` on its successful responses.
+///
+/// Overrides whatever [`CacheRules`] the handler applies, for example for a single route.
+///
+/// # Examples
+/// ```
+/// # use rust_http_server::{handlers::{fs_handler, with_cache_control, IndexStyle}, server::Server};
+/// let handler = with_cache_control(fs_handler("./public", IndexStyle::NotFound), "no-store");
+/// let server = Server::new().get("/live.json", &*handler);
+/// ```
+pub fn with_cache_control(handler: Box<Handler>, value: impl ToString) -> Box<Handler> {
+    let value = value.to_string();
+    Box::new(move |req| {
+        let mut response = handler(req);
+        if matches!(response.status.code, 200..=299 | 304) {
+            response.headers.insert("Cache-Control", &value);
+        }
+        response
+    })
+}
+
+/// Whether `etag` is listed in an `If-Match`/`If-None-Match` header
+fn etag_matches(header: &str, etag: &str, strong: bool) -> bool {
+    let opaque = |tag: &str| tag.strip_prefix("W/").unwrap_or(tag).to_string();
+
+    header.trim() == "*"
+        || header.split(',').map(str::trim).any(|tag| {
+            if strong {
+                !tag.starts_with("W/") && !etag.starts_with("W/") && tag == etag
+            } else {
+                opaque(tag) == opaque(etag)
+            }
+        })
+}
+
+/// Evaluate the conditional headers of `req` in the order of RFC 9110 section 13.2.2.
+///
+/// Returns the status to answer with instead of the representation, if any.
+fn preconditions(req: &Request, etag: &str, modified: Option<SystemTime>) -> Option<u16> {
+    let modified = modified.map(unix_seconds);
+    let date = |name: &str| {
+        req.headers
+            .get(name)
+            .and_then(|date| parse_http_date(date))
+            .map(unix_seconds)
+    };
+
+    if let Some(if_match) = req.headers.get("If-Match") {
+        if !etag_matches(if_match, etag, true) {
+            return Some(412);
+        }
+    } else if let (Some(since), Some(modified)) = (date("If-Unmodified-Since"), modified) {
+        if modified > since {
+            return Some(412);
+        }
+    }
+
+    let get = matches!(req.method, Method::Get | Method::Head);
+    if let Some(if_none_match) = req.headers.get("If-None-Match") {
+        if etag_matches(if_none_match, etag, false) {
+            return Some(if get { 304 } else { 412 });
+        }
+    } else if let (true, Some(since), Some(modified)) = (get, date("If-Modified-Since"), modified) {
+        if modified <= since {
+            return Some(304);
+        }
+    }
+
+    None
+}
+
+/// Ranges beyond this amount are not worth the overhead, the whole file is sent instead
+const MAX_RANGES: usize = 16;
+
+/// Parse a `Range` header into inclusive byte ranges of a file that is `len` bytes long.
+///
+/// Returns [`None`] if the header should be ignored and `Some(Err(()))` if none of the ranges
+/// can be satisfied. Overlapping ranges are merged.
+fn parse_ranges(header: &str, len: u64) -> Option<Result<Vec<(u64, u64)>, ()>> {
+    let (unit, ranges) = header.split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+
+    let mut satisfiable = Vec::new();
+    for range in ranges
+        .split(',')
+        .map(str::trim)
+        .filter(|range| !range.is_empty())
+    {
+        let (start, end) = range.split_once('-')?;
+        let (start, end) = (start.trim(), end.trim());
+
+        let range = if start.is_empty() {
+            // suffix range: the last `end` bytes
+            let suffix = end.parse::<u64>().ok()?;
+            (suffix > 0 && len > 0).then(|| (len.saturating_sub(suffix), len - 1))
+        } else {
+            let start = start.parse::<u64>().ok()?;
+            let end = match end {
+                "" => u64::MAX,
+                end => end.parse::<u64>().ok()?,
+            };
+            if end < start {
+                return None;
+            }
+            (start < len).then(|| (start, end.min(len - 1)))
+        };
+
+        satisfiable.extend(range);
+    }
+
+    if satisfiable.len() > MAX_RANGES {
+        return None;
+    }
+    if satisfiable.is_empty() {
+        return Some(Err(()));
+    }
+
+    let overlapping = satisfiable.iter().enumerate().any(|(i, a)| {
+        satisfiable[i + 1..]
+            .iter()
+            .any(|b| a.0 <= b.1.saturating_add(1) && b.0 <= a.1.saturating_add(1))
+    });
+    if overlapping {
+        satisfiable.sort();
+        satisfiable = satisfiable
+            .into_iter()
+            .fold(Vec::new(), |mut merged, range| {
+                match merged.last_mut() {
+                    Some(last) if range.0 <= last.1.saturating_add(1) => {
+                        last.1 = last.1.max(range.1)
+                    }
+                    _ => merged.push(range),
+                }
+                merged
+            });
+    }
+
+    Some(Ok(satisfiable))
+}
+
+/// Whether a `Range` header should be honoured according to `If-Range`
+fn if_range_matches(req: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    match req.headers.get("If-Range").map(|if_range| if_range.trim()) {
+        None => true,
+        Some(tag) if tag.starts_with('"') || tag.starts_with("W/") => etag_matches(tag, etag, true),
+        // a date only matches exactly, as it is a weak validator otherwise
+        Some(date) => match (parse_http_date(date), modified) {
+            (Some(date), Some(modified)) => unix_seconds(date) == unix_seconds(modified),
+            _ => false,
+        },
+    }
+}
+
+/// Open the file at `path` and read only the bytes of `start..=end`
+fn read_range(path: &Path, (start, end): (u64, u64)) -> std::io::Result<impl Read> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+    Ok(file.take(end - start + 1))
+}
+
+/// Precompressed siblings of a file, in order of preference
+const PRECOMPRESSED: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+
+/// Find the best precompressed sibling of `path` the client accepts.
+///
+/// Returns the sibling and its encoding, and whether any sibling exists at all.
+fn precompressed_sibling(
+    path: &Path,
+    req: &Request,
+    options: &FsOptions,
+) -> (Option<(PathBuf, &'static str)>, bool) {
+    let siblings = PRECOMPRESSED
+        .iter()
+        .filter_map(|(encoding, extension)| {
+            let mut sibling = path.as_os_str().to_owned();
+            sibling.push(".");
+            sibling.push(extension);
+            let sibling = PathBuf::from(sibling);
+
+            // the sibling was not checked by `resolve`, so it may not lead anywhere else
+            let metadata = sibling.symlink_metadata().ok()?;
+            if metadata.is_symlink() && !options.policy.external_symlinks {
+                return None;
+            }
+            sibling.is_file().then_some((sibling, *encoding))
+        })
+        .collect::<Vec<_>>();
+
+    let accept_encoding = req.headers.get("Accept-Encoding");
+    let best = siblings
+        .iter()
+        .map(|(sibling, encoding)| {
+            let quality = accept_encoding.map_or(0.0, |header| quality(header, encoding));
+            (sibling, *encoding, quality)
+        })
+        .filter(|(_, _, quality)| *quality > 0.0)
+        // `max_by` keeps the last of equal elements, but the first one is preferred
+        .rev()
+        .max_by(|a, b| a.2.total_cmp(&b.2))
+        .map(|(sibling, encoding, _)| (sibling.clone(), encoding));
+
+    (best, !siblings.is_empty())
+}
+
+/// Respond with the file at `path` inside `root`, with a `Cache-Control` header if a rule matches
+fn serve_file(path: &Path, root: &str, req: &Request, options: &FsOptions) -> Response {
+    let mut response = file_response(path, req, options);
+
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let relative = relative.components().fold(String::new(), |out, component| {
+        out + "/" + &component.as_os_str().to_string_lossy()
+    });
+    let mime_type = MimeType::get_for_path(&path.to_string_lossy());
+    if let Some(value) = options.cache.get(&relative, &mime_type) {
+        if matches!(response.status.code, 200 | 206 | 304) {
+            response.headers.insert("Cache-Control", value);
+        }
+    }
+
+    response
+}
+
+/// Respond with the contents of the file at `path`, honouring conditional and range requests
+fn file_response(path: &Path, req: &Request, options: &FsOptions) -> Response {
+    let mime_type = MimeType::get_for_path(&path.to_string_lossy());
+
+    let (sibling, vary) = match options.precompressed {
+        true => precompressed_sibling(path, req, options),
+        false => (None, false),
+    };
+    let (path, encoding) = match &sibling {
+        Some((sibling, encoding)) => (sibling.as_path(), Some(*encoding)),
+        None => (path, None),
+    };
+
+    let (file, metadata) = match File::open(path).and_then(|file| {
+        let metadata = file.metadata()?;
+        Ok((file, metadata))
+    }) {
+        Ok(file) => file,
+        Err(..) => return Response::builder().status(500).build(),
+    };
+    let len = metadata.len();
+    let modified = metadata.modified().ok();
+    let etag = format!(
+        r#"{weak}"{len:x}-{modified:x}""#,
+        weak = if options.weak_etags { "W/" } else { "" },
+        modified = modified
+            .and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|modified| modified.as_nanos())
+            .unwrap_or(0),
+    );
+
+    let validators = |builder: ResponseBuilder| {
+        let builder = match vary {
+            true => builder.header("Vary", "Accept-Encoding"),
+            false => builder,
+        };
+        let builder = builder.header("ETag", &etag);
+        match modified {
+            Some(modified) => builder.header("Last-Modified", format_http_date(modified)),
+            None => builder,
+        }
+    };
+
+    if let Some(status) = preconditions(req, &etag, modified) {
+        return validators(Response::builder().status(status)).build();
+    }
+
+    let builder = validators(Response::builder()).header("Accept-Ranges", "bytes");
+    let builder = match encoding {
+        Some(encoding) => builder.header("Content-Encoding", encoding),
+        None => builder,
+    };
+
+    let ranges = match req.headers.get("Range") {
+        Some(range) if req.method == Method::Get && if_range_matches(req, &etag, modified) => {
+            parse_ranges(range, len)
+        }
+        _ => None,
+    };
+
+    match ranges {
+        None => builder
+            .status(200)
+            .header("Content-Type", mime_type)
+            .header("Content-Length", len)
+            .body(Body::Stream(Box::new(file)))
+            .build(),
+        Some(Err(())) => builder
+            .status(416)
+            .header("Content-Range", format!("bytes */{len}"))
+            .build(),
+        Some(Ok(ranges)) if ranges.len() == 1 => {
+            let (start, end) = ranges[0];
+            match read_range(path, (start, end)) {
+                Ok(range) => builder
+                    .status(206)
+                    .header("Content-Type", mime_type)
+                    .header("Content-Range", format!("bytes {start}-{end}/{len}"))
+                    .header("Content-Length", end - start + 1)
+                    .body(Body::Stream(Box::new(range)))
+                    .build(),
+                Err(..) => Response::builder().status(500).build(),
+            }
+        }
+        Some(Ok(ranges)) => {
+            let boundary = format!("{:016x}", RandomState::new().hash_one(&etag));
+
+            let mut length = 0;
+            let mut body: Box<dyn Read + Send> = Box::new(std::io::empty());
+            for (start, end) in ranges {
+                let part = format!(
+                    "--{boundary}\r\nContent-Type: {mime_type}\r\nContent-Range: bytes {start}-{end}/{len}\r\n\r\n"
+                );
+                let range = match read_range(path, (start, end)) {
+                    Ok(range) => range,
+                    Err(..) => return Response::builder().status(500).build(),
+                };
+
+                length += part.len() as u64 + (end - start + 1) + 2;
+                body = Box::new(
+                    body.chain(Cursor::new(part))
+                        .chain(range)
+                        .chain(&b"\r\n"[..]),
+                );
+            }
+            let end = format!("--{boundary}--\r\n");
+            length += end.len() as u64;
+            body = Box::new(body.chain(Cursor::new(end)));
+
+            builder
+                .status(206)
+                .header(
+                    "Content-Type",
+                    format!("multipart/byteranges; boundary={boundary}"),
+                )
+                .header("Content-Length", length)
+                .body(Body::Stream(body))
+                .build()
+        }
+    }
+}
+
+#[test]
+fn test_index_files() {
+    use crate::{server::Server, test_utils::*};
+
+    let dir = temp_dir("index");
+    std::fs::create_dir_all(dir.join("htm")).unwrap();
+    std::fs::create_dir_all(dir.join("empty")).unwrap();
+    std::fs::write(dir.join("index.html"), "html").unwrap();
+    std::fs::write(dir.join("htm/index.htm"), "htm").unwrap();
+
+    let handler = fs_handler(
+        dir.to_str().unwrap(),
+        IndexStyle::IndexFiles(vec!["index.html".to_string(), "index.htm".to_string()]),
+    );
+    let server = Server::new().not_found(&*handler);
+
+    let response = exchange(&server, b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 Ok"));
+    assert!(response.contains("Content-Type: text/html"));
+    assert!(response.ends_with("\r\n\r\nhtml"));
+
+    let response = exchange(&server, b"GET /htm/ HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nhtm"));
+
+    let response = exchange(
+        &server,
+        b"GET /htm?a=b HTTP/1.1\r\nConnection: close\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 301 Moved Permanently"));
+    assert!(response.contains("Location: /htm/?a=b\r\n"));
+
+    let response = exchange(
+        &server,
+        b"GET /empty/ HTTP/1.1\r\nConnection: close\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+
+    // the raw path would be a protocol-relative URL to another host
+    for (path, location) in [
+        ("//evil.com/..", "/"),
+        ("//evil.com/../htm", "/htm/"),
+        ("/htm/./x/%2e%2e", "/htm/"),
+    ] {
+        let response = exchange(
+            &server,
+            format!("GET {path} HTTP/1.1\r\nConnection: close\r\n\r\n").as_bytes(),
+        );
+        assert!(
+            response.contains(&format!("Location: {location}\r\n")),
+            "{path}"
+        );
+    }
+
+    // index files follow the dotfile and symlink policy like any other file
+    std::fs::create_dir_all(dir.join("hidden")).unwrap();
+    std::fs::write(dir.join("hidden/.index.html"), "hidden").unwrap();
+    let handler = fs_handler(
+        dir.to_str().unwrap(),
+        IndexStyle::IndexFile(".index.html".to_string()),
+    );
+    let server = Server::new().not_found(&*handler);
+    let response = exchange(
+        &server,
+        b"GET /hidden/ HTTP/1.1\r\nConnection: close\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+
+    #[cfg(unix)]
+    {
+        let outside = temp_dir("index-outside");
+        std::fs::write(outside.join("index.html"), "secret").unwrap();
+        std::fs::create_dir_all(dir.join("linked")).unwrap();
+        std::os::unix::fs::symlink(outside.join("index.html"), dir.join("linked/index.html"))
+            .unwrap();
+
+        let handler = fs_handler(
+            dir.to_str().unwrap(),
+            IndexStyle::IndexFile("index.html".to_string()),
+        );
+        let server = Server::new().not_found(&*handler);
+        let response = exchange(
+            &server,
+            b"GET /linked/ HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+        assert!(!response.contains("secret"));
+    }
+}
+
+#[test]
+fn test_conditional_requests() {
+    use crate::{server::Server, test_utils::*};
+
+    let dir = temp_dir("conditional");
+    std::fs::write(dir.join("file.txt"), "file").unwrap();
+
+    let handler = fs_handler(dir.to_str().unwrap(), IndexStyle::NotFound);
+    let server = Server::new().not_found(&*handler);
+    let get = |headers: &str| {
+        exchange(
+            &server,
+            format!("GET /file.txt HTTP/1.1\r\nConnection: close\r\n{headers}\r\n").as_bytes(),
+        )
+    };
+    let header = |response: &str, name: &str| {
+        response
+            .lines()
+            .find_map(|line| line.strip_prefix(&format!("{name}: ")))
+            .map(str::to_string)
+            .unwrap()
+    };
+
+    let response = get("");
+    assert!(response.starts_with("HTTP/1.1 200 Ok"));
+    let etag = header(&response, "ETag");
+    let last_modified = header(&response, "Last-Modified");
+    assert!(etag.starts_with('"') && etag.ends_with('"'));
+
+    let response = get(&format!("If-None-Match: \"other\", {etag}\r\n"));
+    assert!(response.starts_with("HTTP/1.1 304 Not Modified"));
+    assert!(response.contains(&format!("ETag: {etag}\r\n")));
+    assert!(response.ends_with("\r\n\r\n"));
+    assert!(!response.contains("Content-Length"));
+
+    // weak comparison for If-None-Match
+    let response = get(&format!("If-None-Match: W/{etag}\r\n"));
+    assert!(response.starts_with("HTTP/1.1 304 Not Modified"));
+
+    let response = get(&format!("If-Modified-Since: {last_modified}\r\n"));
+    assert!(response.starts_with("HTTP/1.1 304 Not Modified"));
+
+    // If-None-Match takes precedence over If-Modified-Since
+    let response = get(&format!(
+        "If-None-Match: \"other\"\r\nIf-Modified-Since: {last_modified}\r\n"
+    ));
+    assert!(response.starts_with("HTTP/1.1 200 Ok"));
+
+    let response = get("If-Modified-Since: Thu, 01 Jan 1970 00:00:00 GMT\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 Ok"));
+
+    let response = get(&format!("If-Match: {etag}\r\n"));
+    assert!(response.starts_with("HTTP/1.1 200 Ok"));
+
+    // strong comparison for If-Match
+    let response = get(&format!("If-Match: W/{etag}\r\n"));
+    assert!(response.starts_with("HTTP/1.1 412 Precondition Failed"));
+
+    let response = get("If-Unmodified-Since: Thu, 01 Jan 1970 00:00:00 GMT\r\n");
+    assert!(response.starts_with("HTTP/1.1 412 Precondition Failed"));
+
+    let response = get(&format!("If-Unmodified-Since: {last_modified}\r\n"));
+    assert!(response.starts_with("HTTP/1.1 200 Ok"));
+}
+
+#[test]
+fn test_parse_ranges() {
+    assert_eq!(parse_ranges("bytes=0-4", 10), Some(Ok(vec![(0, 4)])));
+    assert_eq!(parse_ranges("bytes=5-", 10), Some(Ok(vec![(5, 9)])));
+    assert_eq!(parse_ranges("bytes=-3", 10), Some(Ok(vec![(7, 9)])));
+    assert_eq!(parse_ranges("bytes=-30", 10), Some(Ok(vec![(0, 9)])));
+    assert_eq!(parse_ranges("bytes=8-100", 10), Some(Ok(vec![(8, 9)])));
+    assert_eq!(
+        parse_ranges("bytes=6-7, 0-1", 10),
+        Some(Ok(vec![(6, 7), (0, 1)]))
+    );
+    assert_eq!(
+        parse_ranges("bytes=4-6,0-2,3-3", 10),
+        Some(Ok(vec![(0, 6)]))
+    );
+    assert_eq!(parse_ranges("bytes=10-", 10), Some(Err(())));
+    assert_eq!(parse_ranges("bytes=-0", 10), Some(Err(())));
+    assert_eq!(parse_ranges("bytes=10-, 2-3", 10), Some(Ok(vec![(2, 3)])));
+    assert_eq!(parse_ranges("items=0-1", 10), None);
+    assert_eq!(parse_ranges("bytes=3-1", 10), None);
+    assert_eq!(parse_ranges("bytes=a-b", 10), None);
+}
+
+#[test]
+fn test_range_requests() {
+    use crate::{server::Server, test_utils::*};
+
+    let dir = temp_dir("range");
+    let data = (0..=255).collect::<Vec<u8>>();
+    std::fs::write(dir.join("data.bin"), &data).unwrap();
+    std::fs::write(dir.join("file.txt"), "0123456789").unwrap();
+
+    let handler = fs_handler(dir.to_str().unwrap(), IndexStyle::NotFound);
+    let server = Server::new().not_found(&*handler);
+    let get = |path: &str, headers: &str| {
+        exchange(
+            &server,
+            format!("GET {path} HTTP/1.1\r\nConnection: close\r\n{headers}\r\n").as_bytes(),
+        )
+    };
+
+    let response = get("/file.txt", "");
+    assert!(response.contains("Accept-Ranges: bytes\r\n"));
+    assert!(response.ends_with("\r\n\r\n0123456789"));
+
+    let response = get("/file.txt", "Range: bytes=2-4\r\n");
+    assert!(response.starts_with("HTTP/1.1 206 Partial Content"));
+    assert!(response.contains("Content-Range: bytes 2-4/10\r\n"));
+    assert!(response.contains("Content-Length: 3\r\n"));
+    assert!(response.ends_with("\r\n\r\n234"));
+
+    let response = get("/file.txt", "Range: bytes=20-\r\n");
+    assert!(response.starts_with("HTTP/1.1 416 Range Not Satisfiable"));
+    assert!(response.contains("Content-Range: bytes */10\r\n"));
+
+    let response = get("/file.txt", "Range: bytes=0-0,-2\r\n");
+    assert!(response.starts_with("HTTP/1.1 206 Partial Content"));
+    let boundary = response
+        .split_once("multipart/byteranges; boundary=")
+        .unwrap()
+        .1
+        .split_once("\r\n")
+        .unwrap()
+        .0;
+    let body = response.split_once("\r\n\r\n").unwrap().1;
+    assert_eq!(
+        body,
+        format!(
+            "--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-0/10\r\n\r\n0\r\n\
+             --{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 8-9/10\r\n\r\n89\r\n\
+             --{boundary}--\r\n"
+        )
+    );
+    let length = response
+        .lines()
+        .find_map(|line| line.strip_prefix("Content-Length: "))
+        .unwrap();
+    assert_eq!(length, body.len().to_string());
+
+    let etag = get("/file.txt", "")
+        .lines()
+        .find_map(|line| line.strip_prefix("ETag: ").map(str::to_string))
+        .unwrap();
+    let response = get(
+        "/file.txt",
+        &format!("Range: bytes=0-1\r\nIf-Range: {etag}\r\n"),
+    );
+    assert!(response.starts_with("HTTP/1.1 206 Partial Content"));
+    let response = get("/file.txt", "Range: bytes=0-1\r\nIf-Range: \"stale\"\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 Ok"));
+
+    // binary files are sent untouched
+    let response = exchange_bytes(
+        &server,
+        b"GET /data.bin HTTP/1.1\r\nConnection: close\r\nRange: bytes=250-\r\n\r\n",
+    );
+    assert!(response.ends_with(&data[250..]));
+}
+
+#[test]
+fn test_precompressed() {
+    use crate::{server::Server, test_utils::*};
+
+    let dir = temp_dir("precompressed");
+    std::fs::write(dir.join("app.js"), "plain").unwrap();
+    std::fs::write(dir.join("app.js.gz"), "gzip").unwrap();
+    std::fs::write(dir.join("app.js.br"), "brotli").unwrap();
+    std::fs::write(dir.join("style.css"), "plain").unwrap();
+    std::fs::write(dir.join("style.css.gz"), "gzip").unwrap();
+    std::fs::write(dir.join("other.txt"), "plain").unwrap();
+
+    let handler = fs_handler(dir.to_str().unwrap(), IndexStyle::NotFound);
+    let server = Server::new().not_found(&*handler);
+    let get = |path: &str, headers: &str| {
+        exchange(
+            &server,
+            format!("GET {path} HTTP/1.1\r\nConnection: close\r\n{headers}\r\n").as_bytes(),
+        )
+    };
+
+    let response = get("/app.js", "Accept-Encoding: gzip, deflate, br\r\n");
+    assert!(response.contains("Content-Type: text/javascript\r\n"));
+    assert!(response.contains("Content-Encoding: br\r\n"));
+    assert!(response.contains("Vary: Accept-Encoding\r\n"));
+    assert!(response.ends_with("\r\n\r\nbrotli"));
+
+    let response = get("/app.js", "Accept-Encoding: gzip, br;q=0.5\r\n");
+    assert!(response.contains("Content-Encoding: gzip\r\n"));
+    assert!(response.ends_with("\r\n\r\ngzip"));
+
+    let response = get("/style.css", "Accept-Encoding: br\r\n");
+    assert!(!response.contains("Content-Encoding"));
+    assert!(response.contains("Vary: Accept-Encoding\r\n"));
+    assert!(response.ends_with("\r\n\r\nplain"));
+
+    let response = get("/app.js", "");
+    assert!(!response.contains("Content-Encoding"));
+    assert!(response.ends_with("\r\n\r\nplain"));
+
+    let response = get("/other.txt", "Accept-Encoding: gzip, br\r\n");
+    assert!(!response.contains("Content-Encoding"));
+    assert!(!response.contains("Vary"));
+}
+
+#[test]
+fn test_spa_fallback() {
+    use crate::{server::Server, test_utils::*};
+
+    let dir = temp_dir("spa");
+    std::fs::create_dir_all(dir.join("assets")).unwrap();
+    std::fs::write(dir.join("index.html"), "app").unwrap();
+    std::fs::write(dir.join("assets/app.js"), "js").unwrap();
+
+    let handler = fs_handler_with(
+        dir.to_str().unwrap(),
+        FsOptions::new(IndexStyle::IndexFile("index.html".to_string())).spa_fallback("index.html"),
+    );
+    let server = Server::new().not_found(&*handler);
+    let request = |method: &str, path: &str, accept: &str| {
+        exchange(
+            &server,
+            format!("{method} {path} HTTP/1.1\r\nConnection: close\r\nAccept: {accept}\r\n\r\n")
+                .as_bytes(),
+        )
+    };
+
+    for path in ["/dashboard/settings", "/dashboard/", "/", "/assets/app"] {
+        let response = request("GET", path, "text/html,*/*;q=0.8");
+        assert!(response.starts_with("HTTP/1.1 200 Ok"), "{path}");
+        assert!(response.ends_with("\r\n\r\napp"), "{path}");
+    }
+    assert!(request("GET", "/assets/app.js", "*/*").ends_with("\r\n\r\njs"));
+
+    for (method, path, accept) in [
+        ("GET", "/assets/missing.js", "text/html"),
+        ("GET", "/dashboard/settings", "application/json"),
+        ("GET", "/dashboard/settings", "text/html;q=0"),
+        ("POST", "/dashboard/settings", "text/html"),
+    ] {
+        let response = request(method, path, accept);
+        assert!(
+            response.starts_with("HTTP/1.1 404"),
+            "{method} {path} {accept}"
+        );
+    }
+}
+
+#[test]
+fn test_cache_control() {
+    use crate::{server::Server, test_utils::*};
+
+    let dir = temp_dir("cache");
+    std::fs::create_dir_all(dir.join("assets")).unwrap();
+    std::fs::write(dir.join("index.html"), "html").unwrap();
+    std::fs::write(dir.join("assets/app.js"), "js").unwrap();
+    std::fs::write(dir.join("assets/logo.png"), "png").unwrap();
+    std::fs::write(dir.join("data.json"), "{}").unwrap();
+
+    let options = || {
+        FsOptions::new(IndexStyle::IndexFile("index.html".to_string())).cache(
+            CacheRules::new()
+                .glob("*.js", "max-age=31536000, immutable")
+                .glob("*.html", "no-cache")
+                .mime_type("image/*", "max-age=60"),
+        )
+    };
+    let handler = fs_handler_with(dir.to_str().unwrap(), options());
+    let live = with_cache_control(
+        fs_handler_with(dir.to_str().unwrap(), options()),
+        "no-store",
+    );
+    let server = Server::new().get("/data.json", &*live).not_found(&*handler);
+    let cache_control = |path: &str, headers: &str| {
+        let response = exchange(
+            &server,
+            format!("GET {path} HTTP/1.1\r\nConnection: close\r\n{headers}\r\n").as_bytes(),
+        );
+        response
+            .lines()
+            .find_map(|line| line.strip_prefix("Cache-Control: "))
+            .map(str::to_string)
+    };
+
+    assert_eq!(
+        cache_control("/assets/app.js", "").as_deref(),
+        Some("max-age=31536000, immutable")
+    );
+    assert_eq!(cache_control("/", "").as_deref(), Some("no-cache"));
+    assert_eq!(
+        cache_control("/index.html", "").as_deref(),
+        Some("no-cache")
+    );
+    assert_eq!(
+        cache_control("/assets/logo.png", "").as_deref(),
+        Some("max-age=60")
+    );
+    assert_eq!(cache_control("/data.json", "").as_deref(), Some("no-store"));
+    assert_eq!(cache_control("/assets/missing.js", ""), None);
+
+    // 304s carry the same caching headers as the 200 they stand in for
+    assert_eq!(
+        cache_control(
+            "/assets/app.js",
+            "If-Modified-Since: Fri, 31 Dec 9999 23:59:59 GMT\r\n"
+        )
+        .as_deref(),
+        Some("max-age=31536000, immutable")
+    );
+}
@@ -0,0 +1,661 @@
+//! JSON values, a strict parser and a serializer
+
+use std::{
+    error::Error,
+    fmt::{Display, Write},
+    str::FromStr,
+};
+
+/// Arrays and objects nested deeper than this are rejected instead of overflowing the stack
+const MAX_DEPTH: usize = 128;
+
+#[derive(Clone, PartialEq, Debug)]
+/// A JSON value, objects keep the order their members were inserted in
+pub enum JsonValue {
+    /// `null`
+    Null,
+    /// `true` or `false`
+    Bool(bool),
+    /// Any number, integers are exact up to 2^53
+    Number(f64),
+    /// A string
+    String(String),
+    /// An array
+    Array(Vec<JsonValue>),
+    /// An object
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    /// Build an object from `(key, value)` pairs
+    ///
+    /// # Examples
+    /// ```
+    /// # use rust_http_server::json::JsonValue;
+    /// let value = JsonValue::object([("a", 1.into()), ("b", "two".into())]);
+    /// assert_eq!(value.to_string(), r#"{"a":1,"b":"two"}"#);
+    /// ```
+    pub fn object<K: ToString>(members: impl IntoIterator<Item = (K, JsonValue)>) -> Self {
+        Self::Object(
+            members
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    /// Parse `input` as a single JSON value according to RFC 8259.
+    ///
+    /// Nothing but whitespace may surround the value, extensions like comments or trailing
+    /// commas are errors.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rust_http_server::json::JsonValue;
+    /// let value = JsonValue::parse(r#"{"name": "test", "tags": ["a", "b"]}"#).unwrap();
+    /// assert_eq!(value.get("name").and_then(JsonValue::as_str), Some("test"));
+    ///
+    /// let err = JsonValue::parse("{\n  \"a\": 1,\n}").unwrap_err();
+    /// assert_eq!((err.line, err.column), (3, 1));
+    /// assert_eq!(err.to_string(), "expected a string key at line 3, column 1");
+    /// ```
+    pub fn parse(input: &str) -> Result<Self, JsonError> {
+        let mut parser = Parser {
+            input,
+            pos: 0,
+            depth: 0,
+        };
+        parser.whitespace();
+        let value = parser.value()?;
+        parser.whitespace();
+
+        match parser.pos < input.len() {
+            true => Err(parser.error("unexpected characters after the value")),
+            false => Ok(value),
+        }
+    }
+
+    /// The member `key` of an object, the first one if it appears more than once
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            Self::Object(members) => members
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /// The value if it is a string
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The value if it is a number
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// The value if it is a boolean
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// The items if the value is an array
+    pub fn as_array(&self) -> Option<&[JsonValue]> {
+        match self {
+            Self::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for JsonValue {
+    type Err = JsonError;
+
+    /// Same as [`JsonValue::parse`]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+/// Why JSON could not be parsed and where
+pub struct JsonError {
+    /// What is wrong
+    pub message: &'static str,
+    /// Byte offset into the input
+    pub offset: usize,
+    /// Line of the offset, starting at `1`
+    pub line: usize,
+    /// Character in the line of the offset, starting at `1`
+    pub column: usize,
+}
+
+impl JsonError {
+    /// Error at byte `offset` of `input`
+    pub(crate) fn at(input: &[u8], offset: usize, message: &'static str) -> Self {
+        let before = &input[..offset];
+        let line_start = before
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |newline| newline + 1);
+
+        Self {
+            message,
+            offset,
+            line: before.iter().filter(|&&b| b == b'\n').count() + 1,
+            // continuation bytes of UTF-8 do not start a character
+            column: before[line_start..]
+                .iter()
+                .filter(|&&b| b & 0xc0 != 0x80)
+                .count()
+                + 1,
+        }
+    }
+}
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at line {}, column {}",
+            self.message, self.line, self.column
+        )
+    }
+}
+
+impl Error for JsonError {}
+
+/// Recursive descent over the input, see [`JsonValue::parse`]
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &'static str) -> JsonError {
+        JsonError::at(self.input.as_bytes(), self.pos, message)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.as_bytes().get(self.pos).copied()
+    }
+
+    /// Skip the byte `expected` if it is next
+    fn eat(&mut self, expected: u8) -> bool {
+        let found = self.peek() == Some(expected);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn value(&mut self) -> Result<JsonValue, JsonError> {
+        match self.peek() {
+            Some(b'n') => self.literal("null", JsonValue::Null),
+            Some(b't') => self.literal("true", JsonValue::Bool(true)),
+            Some(b'f') => self.literal("false", JsonValue::Bool(false)),
+            Some(b'"') => self.string().map(JsonValue::String),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(b'[') => self.nested(Self::array),
+            Some(b'{') => self.nested(Self::object),
+            Some(..) => Err(self.error("expected a value")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: JsonValue) -> Result<JsonValue, JsonError> {
+        match self.input[self.pos..].starts_with(word) {
+            true => {
+                self.pos += word.len();
+                Ok(value)
+            }
+            false => Err(self.error("expected a value")),
+        }
+    }
+
+    /// Run `parse` for an array or object one level deeper
+    fn nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<JsonValue, JsonError>,
+    ) -> Result<JsonValue, JsonError> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("nested too deeply"));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn digits(&mut self) -> usize {
+        let start = self.pos;
+        while self.peek().is_some_and(|b| b.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+
+    fn number(&mut self) -> Result<JsonValue, JsonError> {
+        let start = self.pos;
+        self.eat(b'-');
+
+        match self.peek() {
+            Some(b'0') => {
+                self.pos += 1;
+                if self.peek().is_some_and(|b| b.is_ascii_digit()) {
+                    return Err(self.error("leading zeros are not allowed"));
+                }
+            }
+            Some(b'1'..=b'9') => {
+                self.digits();
+            }
+            _ => return Err(self.error("expected a digit")),
+        }
+        if self.eat(b'.') && self.digits() == 0 {
+            return Err(self.error("expected a digit"));
+        }
+        if self.eat(b'e') || self.eat(b'E') {
+            let _ = self.eat(b'+') || self.eat(b'-');
+            if self.digits() == 0 {
+                return Err(self.error("expected a digit"));
+            }
+        }
+
+        match self.input[start..self.pos].parse::<f64>() {
+            Ok(n) if n.is_finite() => Ok(JsonValue::Number(n)),
+            _ => {
+                self.pos = start;
+                Err(self.error("number is too large"))
+            }
+        }
+    }
+
+    /// Four hex digits of a `\u` escape
+    fn hex(&mut self) -> Result<u32, JsonError> {
+        let digits = self.input.get(self.pos..self.pos + 4);
+        match digits.filter(|digits| digits.bytes().all(|b| b.is_ascii_hexdigit())) {
+            Some(digits) => {
+                self.pos += 4;
+                Ok(u32::from_str_radix(digits, 16).unwrap())
+            }
+            None => Err(self.error("expected four hex digits")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.pos += 1;
+        let mut string = String::new();
+        let mut start = self.pos;
+
+        loop {
+            match self.peek() {
+                Some(b'"') => {
+                    string += &self.input[start..self.pos];
+                    self.pos += 1;
+                    return Ok(string);
+                }
+                Some(b'\\') => {
+                    string += &self.input[start..self.pos];
+                    self.pos += 1;
+                    let escape = self.peek();
+                    self.pos += 1;
+                    string.push(match escape {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => self.unicode_escape()?,
+                        _ => {
+                            self.pos -= 1;
+                            return Err(self.error("invalid escape"));
+                        }
+                    });
+                    start = self.pos;
+                }
+                Some(0..=0x1f) => return Err(self.error("control character in string")),
+                Some(..) => self.pos += 1,
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    /// The character of a `\u` escape, including a following low surrogate
+    fn unicode_escape(&mut self) -> Result<char, JsonError> {
+        let start = self.pos - 2;
+        let high = self.hex()?;
+        let code = match high {
+            0xd800..=0xdbff if self.input[self.pos..].starts_with("\\u") => {
+                self.pos += 2;
+                match self.hex()? {
+                    low @ 0xdc00..=0xdfff => 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00),
+                    _ => 0xd800,
+                }
+            }
+            code => code,
+        };
+
+        char::from_u32(code).ok_or_else(|| {
+            self.pos = start;
+            self.error("unpaired surrogate")
+        })
+    }
+
+    fn array(&mut self) -> Result<JsonValue, JsonError> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        self.whitespace();
+        if self.eat(b']') {
+            return Ok(JsonValue::Array(items));
+        }
+
+        loop {
+            self.whitespace();
+            items.push(self.value()?);
+            self.whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(JsonValue::Array(items));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<JsonValue, JsonError> {
+        self.pos += 1;
+        let mut members = Vec::new();
+        self.whitespace();
+        if self.eat(b'}') {
+            return Ok(JsonValue::Object(members));
+        }
+
+        loop {
+            self.whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a string key"));
+            }
+            let key = self.string()?;
+            self.whitespace();
+            if !self.eat(b':') {
+                return Err(self.error("expected ':'"));
+            }
+            self.whitespace();
+            members.push((key, self.value()?));
+            self.whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(JsonValue::Object(members));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut impl Write, s: &str) -> std::fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if u32::from(c) < 0x20 => write!(f, "\\u{:04x}", u32::from(c))?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+impl JsonValue {
+    /// Write the value, `indent` is the current depth when pretty printing
+    fn write(&self, f: &mut std::fmt::Formatter<'_>, indent: Option<usize>) -> std::fmt::Result {
+        let newline = |f: &mut std::fmt::Formatter<'_>, depth: usize| match indent {
+            Some(..) => write!(f, "\n{:1$}", "", depth * 2),
+            None => Ok(()),
+        };
+        let depth = indent.unwrap_or_default();
+        let next = indent.map(|depth| depth + 1);
+
+        match self {
+            Self::Null => f.write_str("null"),
+            Self::Bool(b) => write!(f, "{b}"),
+            // JSON has no NaN or infinity
+            Self::Number(n) if !n.is_finite() => f.write_str("null"),
+            Self::Number(n) => write!(f, "{n}"),
+            Self::String(s) => write_string(f, s),
+            Self::Array(items) if items.is_empty() => f.write_str("[]"),
+            Self::Array(items) => {
+                f.write_char('[')?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    newline(f, depth + 1)?;
+                    item.write(f, next)?;
+                }
+                newline(f, depth)?;
+                f.write_char(']')
+            }
+            Self::Object(members) if members.is_empty() => f.write_str("{}"),
+            Self::Object(members) => {
+                f.write_char('{')?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    newline(f, depth + 1)?;
+                    write_string(f, key)?;
+                    f.write_str(if indent.is_some() { ": " } else { ":" })?;
+                    value.write(f, next)?;
+                }
+                newline(f, depth)?;
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+impl Display for JsonValue {
+    /// Compact JSON without any whitespace, or indented by two spaces with `{:#}`
+    ///
+    /// # Examples
+    /// ```
+    /// # use rust_http_server::json::JsonValue;
+    /// let value = JsonValue::object([("a", vec![1, 2].into())]);
+    /// assert_eq!(value.to_string(), r#"{"a":[1,2]}"#);
+    /// assert_eq!(format!("{value:#}"), "{\n  \"a\": [\n    1,\n    2\n  ]\n}");
+    /// ```
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write(f, f.alternate().then_some(0))
+    }
+}
+
+impl From<bool> for JsonValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+macro_rules! from_number {
+    ($($t: ty),*) => {
+        $(
+            impl From<$t> for JsonValue {
+                fn from(value: $t) -> Self {
+                    Self::Number(value as f64)
+                }
+            }
+        )*
+    };
+}
+
+from_number!(i32, i64, u32, u64, usize, f32, f64);
+
+impl From<&str> for JsonValue {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+
+impl From<String> for JsonValue {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+impl<T: Into<JsonValue>> From<Option<T>> for JsonValue {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Self::Null, Into::into)
+    }
+}
+
+impl<T: Into<JsonValue>> From<Vec<T>> for JsonValue {
+    fn from(value: Vec<T>) -> Self {
+        Self::Array(value.into_iter().map(Into::into).collect())
+    }
+}
+
+#[test]
+fn test_serialize() {
+    let value = JsonValue::object([
+        ("null", JsonValue::Null),
+        ("bool", true.into()),
+        ("int", 42.into()),
+        ("float", 1.5.into()),
+        ("nan", f64::NAN.into()),
+        ("escaped", "\"quoted\"\\\n\u{1}é".into()),
+        ("array", vec![1, 2].into()),
+        ("empty", JsonValue::object::<&str>([])),
+    ]);
+
+    assert_eq!(
+        value.to_string(),
+        r#"{"null":null,"bool":true,"int":42,"float":1.5,"nan":null,"escaped":"\"quoted\"\\\n\u0001é","array":[1,2],"empty":{}}"#
+    );
+}
+
+#[test]
+fn test_parse() {
+    let value = JsonValue::parse(
+        " {\"a\": [1, -0.5, 2e3, true, false, null], \"s\": \"\\\"\\\\\\/\\b\\f\\n\\r\\t\\u00e9\\ud83d\\ude00\", \"o\": {}} ",
+    )
+    .unwrap();
+    assert_eq!(
+        value,
+        JsonValue::object([
+            (
+                "a",
+                JsonValue::Array(vec![
+                    1.into(),
+                    (-0.5).into(),
+                    2000.into(),
+                    true.into(),
+                    false.into(),
+                    JsonValue::Null,
+                ])
+            ),
+            ("s", "\"\\/\u{8}\u{c}\n\r\té😀".into()),
+            ("o", JsonValue::object::<&str>([])),
+        ])
+    );
+    assert_eq!(JsonValue::parse(&value.to_string()), Ok(value.clone()));
+    assert_eq!(JsonValue::parse(&format!("{value:#}")), Ok(value));
+
+    for (input, message, column) in [
+        ("", "unexpected end of input", 1),
+        ("[1,]", "expected a value", 4),
+        ("{\"a\":1,}", "expected a string key", 8),
+        ("{'a':1}", "expected a string key", 2),
+        ("01", "leading zeros are not allowed", 2),
+        ("1.", "expected a digit", 3),
+        ("-", "expected a digit", 2),
+        ("1e400", "number is too large", 1),
+        ("\"é\t\"", "control character in string", 3),
+        ("\"\\x\"", "invalid escape", 3),
+        ("\"\\ud800\"", "unpaired surrogate", 2),
+        ("\"abc", "unterminated string", 5),
+        ("[1 2]", "expected ',' or ']'", 4),
+        ("nul", "expected a value", 1),
+        ("{} {}", "unexpected characters after the value", 4),
+    ] {
+        let err = JsonValue::parse(input).unwrap_err();
+        assert_eq!(
+            (err.message, err.line, err.column),
+            (message, 1, column),
+            "{input}"
+        );
+    }
+
+    let deep = "[".repeat(MAX_DEPTH + 1) + &"]".repeat(MAX_DEPTH + 1);
+    assert_eq!(
+        JsonValue::parse(&deep).unwrap_err().message,
+        "nested too deeply"
+    );
+}
+
+#[test]
+fn test_request_json() {
+    use crate::{request::Request, response::Response, server::Server, test_utils::exchange};
+
+    let handler = |req: &Request| match req.json() {
+        Some(Ok(value)) => Response::builder().json(value).build(),
+        Some(Err(err)) => Response::builder()
+            .status(400)
+            .body(err.to_string())
+            .build(),
+        None => Response::builder().status(415).build(),
+    };
+    let server = Server::new().not_found(&handler);
+    let request = |method: &str, content_type: &str, body: &str| {
+        exchange(
+            &server,
+            format!(
+                "{method} / HTTP/1.1\r\nConnection: close\r\nContent-Type: {content_type}\r\n\
+                 Content-Length: {}\r\n\r\n{body}",
+                body.len()
+            )
+            .as_bytes(),
+        )
+    };
+
+    for method in ["POST", "PUT"] {
+        let response = request(
+            method,
+            "application/json; charset=utf-8",
+            "[1, {\"a\": null}]",
+        );
+        assert!(response.contains("Content-Type: application/json\r\n"));
+        assert!(response.ends_with("\r\n\r\n[1,{\"a\":null}]"));
+    }
+    assert!(request("PATCH", "application/merge-patch+json", "{}").ends_with("\r\n\r\n{}"));
+    assert!(request("POST", "text/plain", "{}").starts_with("HTTP/1.1 415"));
+    assert!(request("POST", "application/json", "{\n\"a\" 1}")
+        .ends_with("expected ':' at line 2, column 5"));
+}
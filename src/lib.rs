@@ -3,12 +3,30 @@
 
 #![warn(missing_docs)]
 
+mod archive;
+pub mod auth;
+pub mod cache;
 pub mod common;
+pub mod compression;
+pub mod cookie;
+pub mod cors;
+pub mod crypto;
+pub mod date;
 pub mod handlers;
+pub mod json;
+pub mod limits;
+mod listing;
 pub mod mime_types;
+pub mod multipart;
+pub mod path;
 pub mod request;
 pub mod response;
 pub mod server;
+pub mod session;
+mod webdav;
+
+#[cfg(test)]
+mod test_utils;
 
 // #[cfg(feature = "git")]
 // pub mod git;
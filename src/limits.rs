@@ -0,0 +1,83 @@
+//! Bounds applied to incoming requests and connections
+
+use std::time::Duration;
+
+/// Upper bounds enforced while parsing a [`Request`](crate::request::Request)
+///
+/// Exceeding one of them stops parsing and answers the client with an error status.
+///
+/// # Examples
+/// ```
+/// # use rust_http_server::{limits::Limits, server::Server};
+/// let server = Server::new().limits(Limits {
+///     max_body: 1024 * 1024,
+///     ..Default::default()
+/// });
+/// ```
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// Maximum length of the request line in bytes (`414 URI Too Long`)
+    pub max_request_line: usize,
+    /// Maximum combined length of all header lines in bytes (`431 Request Header Fields Too Large`)
+    pub max_header_bytes: usize,
+    /// Maximum number of headers (`431 Request Header Fields Too Large`)
+    pub max_headers: usize,
+    /// Maximum length of the body in bytes (`413 Payload Too Large`)
+    pub max_body: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_request_line: 8 * 1024,
+            max_header_bytes: 64 * 1024,
+            max_headers: 100,
+            max_body: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// Timeouts applied to every connection accepted by a [`Server`](crate::server::Server)
+///
+/// [`None`] disables the respective timeout. Running out of time while a request is being received
+/// is answered with `408 Request Timeout`, idle connections are closed silently.
+///
+/// # Examples
+/// ```
+/// # use rust_http_server::{limits::Timeouts, server::Server};
+/// # use std::time::Duration;
+/// let server = Server::new().timeouts(Timeouts {
+///     header_read: Some(Duration::from_secs(5)),
+///     ..Default::default()
+/// });
+/// ```
+#[derive(Clone, Debug)]
+pub struct Timeouts {
+    /// Time to wait for the first byte of a request
+    pub idle: Option<Duration>,
+    /// Time allowed for receiving the request line and all headers, starting at the first byte
+    pub header_read: Option<Duration>,
+    /// Time allowed for receiving the whole body
+    pub body_read: Option<Duration>,
+    /// Time allowed for a single write to the client
+    pub write: Option<Duration>,
+    /// Minimum average rate in bytes per second at which a body has to arrive.
+    ///
+    /// Only checked once the body has been transferring for [`MIN_RATE_GRACE`].
+    pub min_body_rate: Option<u64>,
+}
+
+/// Time a body is given before [`Timeouts::min_body_rate`] is enforced
+pub const MIN_RATE_GRACE: Duration = Duration::from_secs(1);
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            idle: Some(Duration::from_secs(60)),
+            header_read: Some(Duration::from_secs(10)),
+            body_read: Some(Duration::from_secs(300)),
+            write: Some(Duration::from_secs(30)),
+            min_body_rate: Some(1024),
+        }
+    }
+}
@@ -0,0 +1,399 @@
+//! Directory listings for [`IndexStyle::IndexDirectory`](crate::handlers::IndexStyle::IndexDirectory)
+
+use crate::{
+    common::{add_vary, html_escape, media_quality, percent_decode, percent_encode},
+    date::format_rfc3339,
+    json::JsonValue,
+    path::{normalize, DotFiles, PathPolicy},
+    request::Request,
+    response::Response,
+};
+use std::{cmp::Ordering, path::Path, time::SystemTime};
+
+/// A file or directory inside a listed directory
+pub(crate) struct Entry {
+    pub name: String,
+    pub is_dir: bool,
+    /// Size in bytes, `0` for directories
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SortKey {
+    Name,
+    Size,
+    Modified,
+}
+
+impl SortKey {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Name => "name",
+            Self::Size => "size",
+            Self::Modified => "mtime",
+        }
+    }
+}
+
+/// Read the entries of the directory at `path`, leaving out dotfiles unless `policy` allows them
+pub(crate) fn read_entries(path: &Path, policy: &PathPolicy) -> std::io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        if policy.dotfiles != DotFiles::Allow && name.starts_with('.') {
+            continue;
+        }
+
+        // symlinks are described by what they point to, dangling ones by themselves
+        let Ok(metadata) = entry.path().metadata().or_else(|_| entry.metadata()) else {
+            continue;
+        };
+        entries.push(Entry {
+            name,
+            is_dir: metadata.is_dir(),
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified: metadata.modified().ok(),
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Sort `entries` by the `sort` (`name`, `size` or `mtime`) and `order` (`asc` or `desc`)
+/// search params of `req`, directories always come first
+fn sort_entries(entries: &mut [Entry], req: &Request) -> (SortKey, bool) {
+    let key = match req.search.get("sort").map(String::as_str) {
+        Some("size") => SortKey::Size,
+        Some("mtime") => SortKey::Modified,
+        _ => SortKey::Name,
+    };
+    let descending = req.search.get("order").is_some_and(|order| order == "desc");
+
+    entries.sort_by(|a, b| {
+        let ordering = match key {
+            SortKey::Name => Ordering::Equal,
+            SortKey::Size => a.size.cmp(&b.size),
+            SortKey::Modified => a.modified.cmp(&b.modified),
+        }
+        .then_with(|| a.name.cmp(&b.name));
+
+        b.is_dir.cmp(&a.is_dir).then(if descending {
+            ordering.reverse()
+        } else {
+            ordering
+        })
+    });
+
+    (key, descending)
+}
+
+/// Human readable size, e.g. `1.5 KiB`
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{size} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// Links to every directory leading up to the listed one.
+///
+/// All links are relative, so they keep working wherever the directory is served from.
+fn breadcrumbs(pathname: &str) -> String {
+    let segments = pathname
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>();
+    let link = |depth: usize, name: &str| {
+        let href = segments[..depth]
+            .iter()
+            .fold("/".to_string(), |href, segment| href + segment + "/");
+        format!(
+            r#"<a href="{}">{}</a>"#,
+            html_escape(&href),
+            html_escape(name)
+        )
+    };
+
+    segments
+        .iter()
+        .enumerate()
+        .fold(link(0, "/"), |out, (i, segment)| {
+            out + &link(i + 1, &decode_lossy(segment)) + "/"
+        })
+}
+
+/// Listing of the directory at `path`, as JSON if `req` asks for it with `?format=json` or
+/// prefers `application/json` over `text/html`, as HTML otherwise.
+///
+/// The HTML listing links to archives of the directory if `archives` is set and has a form to
+/// upload files into it if `upload` is set.
+pub(crate) fn listing(
+    path: &Path,
+    req: &Request,
+    policy: &PathPolicy,
+    archives: bool,
+    upload: bool,
+) -> Response {
+    let mut entries = match read_entries(path, policy) {
+        Ok(entries) => entries,
+        Err(..) => return Response::builder().status(500).build(),
+    };
+    let sort = sort_entries(&mut entries, req);
+
+    let json = match req.search.get("format").map(String::as_str) {
+        Some("json") => true,
+        Some(..) => false,
+        None => {
+            let accept = req.headers.get("Accept").map(String::as_str);
+            media_quality(accept, "application/json") > media_quality(accept, "text/html")
+        }
+    };
+
+    // the request may spell the directory with `//`, `.` or `..` segments
+    let pathname = match normalize(&req.uri.path) {
+        pathname if pathname.ends_with('/') => pathname,
+        pathname => pathname + "/",
+    };
+    let mut response = if json {
+        json_listing(&entries, &pathname)
+    } else {
+        html_listing(&entries, sort, &pathname, req, archives, upload)
+    };
+    add_vary(&mut response.headers, "Accept");
+    response
+}
+
+/// JSON listing, an object with the `path` of the directory at `pathname` and its `entries`
+fn json_listing(entries: &[Entry], pathname: &str) -> Response {
+    let entries = entries
+        .iter()
+        .map(|entry| {
+            let trailing = if entry.is_dir { "/" } else { "" };
+            JsonValue::object([
+                ("name", entry.name.as_str().into()),
+                (
+                    "type",
+                    if entry.is_dir { "directory" } else { "file" }.into(),
+                ),
+                ("size", entry.size.into()),
+                ("mtime", entry.modified.map(format_rfc3339).into()),
+                (
+                    "url",
+                    format!("{pathname}{}{trailing}", percent_encode(&entry.name)).into(),
+                ),
+            ])
+        })
+        .collect::<Vec<_>>();
+
+    Response::builder()
+        .status(200)
+        .json(JsonValue::object([
+            ("path", decode_lossy(pathname).into()),
+            ("entries", JsonValue::Array(entries)),
+        ]))
+        .build()
+}
+
+fn decode_lossy(s: &str) -> String {
+    percent_decode(s)
+        .map(|s| String::from_utf8_lossy(&s).into_owned())
+        .unwrap_or(s.to_string())
+}
+
+/// HTML listing of the directory at `pathname` with sortable columns
+fn html_listing(
+    entries: &[Entry],
+    (key, descending): (SortKey, bool),
+    pathname: &str,
+    req: &Request,
+    archives: bool,
+    upload: bool,
+) -> Response {
+    let header = |column: SortKey, label: &str| {
+        let (order, arrow) = match (column == key, descending) {
+            (true, false) => ("desc", " ▲"),
+            (true, true) => ("asc", " ▼"),
+            (false, _) => ("asc", ""),
+        };
+        format!(
+            r#"<a href="?sort={}&amp;order={order}">{label}</a>{arrow}"#,
+            column.as_str()
+        )
+    };
+
+    let mut rows = String::new();
+    // the parent of a mounted directory may not be served at all
+    if !req.pathname.trim_matches('/').is_empty() {
+        rows += "            <tr><td>⬆️ <a href=\"../\">../</a></td><td></td><td></td></tr>\n";
+    }
+    for entry in entries {
+        let (icon, trailing, size) = if entry.is_dir {
+            ("📁", "/", "-".to_string())
+        } else {
+            ("📄", "", format_size(entry.size))
+        };
+        let modified = entry.modified.map(format_rfc3339).unwrap_or_default();
+
+        rows += &format!(
+            "            <tr><td>{icon} <a href=\"./{href}{trailing}\">{name}{trailing}</a></td>\
+             <td class=\"size\" title=\"{bytes} bytes\">{size}</td>\
+             <td><time datetime=\"{modified}\">{display}</time></td></tr>\n",
+            href = percent_encode(&entry.name),
+            name = html_escape(&entry.name),
+            bytes = entry.size,
+            display = modified.replace('T', " ").replace('Z', ""),
+        );
+    }
+
+    Response::builder()
+        .status(200)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .body(format!(
+            include_str!("./default_pages/listing.html"),
+            title = html_escape(&decode_lossy(pathname)),
+            breadcrumbs = breadcrumbs(pathname),
+            rows = rows,
+            downloads = if archives {
+                r#"<p>Download as <a href="?download=tar">tar</a> or <a href="?download=zip">zip</a></p>"#
+            } else {
+                ""
+            },
+            upload = if upload {
+                r#"<form method="post" enctype="multipart/form-data"><input type="file" name="file" multiple required> <button>Upload</button></form>"#
+            } else {
+                ""
+            },
+            name_header = header(SortKey::Name, "Name"),
+            size_header = header(SortKey::Size, "Size"),
+            modified_header = header(SortKey::Modified, "Last modified"),
+        ))
+        .build()
+}
+
+#[test]
+fn test_directory_listing() {
+    use crate::{
+        handlers::{fs_handler, IndexStyle},
+        server::Server,
+        test_utils::*,
+    };
+
+    let dir = temp_dir("listing");
+    std::fs::create_dir_all(dir.join("sub dir/nested")).unwrap();
+    std::fs::write(dir.join("small.txt"), "a").unwrap();
+    std::fs::write(dir.join("big.txt"), "a".repeat(2048)).unwrap();
+    std::fs::write(dir.join("<script>&.txt"), "").unwrap();
+    std::fs::write(dir.join(".hidden"), "").unwrap();
+
+    let handler = fs_handler(dir.to_str().unwrap(), IndexStyle::IndexDirectory);
+    let server = Server::new().not_found(&*handler);
+    let get = |path: &str| {
+        exchange(
+            &server,
+            format!("GET {path} HTTP/1.1\r\nConnection: close\r\n\r\n").as_bytes(),
+        )
+    };
+
+    let response = get("/");
+    assert!(response.starts_with("HTTP/1.1 200 Ok"));
+    assert!(!response.contains("href=\"../\""));
+    assert!(!response.contains(".hidden"));
+    assert!(!response.contains("<script>"));
+    assert!(response.contains(r#"<a href="./%3Cscript%3E%26.txt">&lt;script&gt;&amp;.txt</a>"#));
+    assert!(response.contains(r#"<a href="./sub%20dir/">sub dir/</a>"#));
+    assert!(response.contains("2.0 KiB"));
+
+    let position = |response: &str, name: &str| response.find(name).unwrap();
+    assert!(position(&response, "sub dir/") < position(&response, "big.txt"));
+    assert!(position(&response, "big.txt") < position(&response, "small.txt"));
+
+    let response = get("/?sort=size&order=asc");
+    assert!(position(&response, "sub dir/") < position(&response, "small.txt"));
+    assert!(position(&response, "small.txt") < position(&response, "big.txt"));
+    assert!(response.contains(r#"<a href="?sort=size&amp;order=desc">Size</a> ▲"#));
+
+    let response = get("/sub%20dir/nested/");
+    assert!(response.contains("<title>Index of /sub dir/nested/</title>"));
+    assert!(response.contains(r#"<a href="../">../</a>"#));
+    assert!(response.contains(
+        r#"<a href="/">/</a><a href="/sub%20dir/">sub dir</a>/<a href="/sub%20dir/nested/">nested</a>/"#
+    ));
+
+    // links are built from the directory, not from how the request spelled it
+    let response = get("/small.txt/..//sub%20dir/./nested/");
+    assert!(response.contains("<title>Index of /sub dir/nested/</title>"));
+    assert!(response.contains(r#"<a href="/">/</a><a href="/sub%20dir/">sub dir</a>/"#));
+
+    // archives are opt-in, without them `download` is ignored
+    let response = get("/?download=zip");
+    assert!(response.starts_with("HTTP/1.1 200 Ok"));
+    assert!(response.contains("<title>Index of /</title>"));
+    assert!(!response.contains("download="));
+}
+
+#[test]
+fn test_json_listing() {
+    use crate::{
+        handlers::{fs_handler, IndexStyle},
+        server::Server,
+        test_utils::*,
+    };
+
+    let dir = temp_dir("json-listing");
+    std::fs::create_dir_all(dir.join("sub dir")).unwrap();
+    std::fs::write(dir.join("sub dir/a\"b.txt"), "abc").unwrap();
+
+    let handler = fs_handler(dir.to_str().unwrap(), IndexStyle::IndexDirectory);
+    let server = Server::new().not_found(&*handler);
+    let get = |path: &str, accept: &str| {
+        exchange(
+            &server,
+            format!("GET {path} HTTP/1.1\r\nConnection: close\r\nAccept: {accept}\r\n\r\n")
+                .as_bytes(),
+        )
+    };
+
+    let response = get("/sub%20dir/?format=json", "*/*");
+    assert!(response.contains("Content-Type: application/json"));
+    assert!(response.contains("Vary: Accept"));
+    let body = response.split_once("\r\n\r\n").unwrap().1;
+    assert!(body.starts_with(
+        r#"{"path":"/sub dir/","entries":[{"name":"a\"b.txt","type":"file","size":3,"mtime":""#
+    ));
+    assert!(body.ends_with(r#"","url":"/sub%20dir/a%22b.txt"}]}"#));
+    let response = get("//sub%20dir//?format=json", "*/*");
+    assert!(response.contains(r#"{"path":"/sub dir/","#));
+    assert!(response.contains(r#""url":"/sub%20dir/a%22b.txt"}"#));
+
+    let response = get("/", "application/json");
+    assert!(response.contains(
+        r#"{"path":"/","entries":[{"name":"sub dir","type":"directory","size":0,"mtime":""#
+    ));
+    assert!(response.contains(r#""url":"/sub%20dir/"}"#));
+
+    for (path, accept) in [
+        ("/", "text/html,application/xhtml+xml,*/*;q=0.8"),
+        ("/", "*/*"),
+        ("/?format=html", "application/json"),
+    ] {
+        assert!(
+            get(path, accept).contains("Content-Type: text/html"),
+            "{path} {accept}"
+        );
+    }
+}
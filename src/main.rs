@@ -1,26 +1,40 @@
 use rust_http_server::{
-    handlers::{not_found_handler_default, IndexStyle},
-    mime_types::MimeType,
-    request::Request,
-    response::Response,
+    auth::{basic_auth, BasicAuth},
+    cache::CacheRules,
+    compression::{compression, Compression},
+    cors::{cors, Cors},
+    handlers::{fs_handler_with, FsOptions, IndexStyle},
+    limits::Limits,
+    path::DotFiles,
+    server::Server,
 };
-use std::{
-    error::Error, io::BufRead, net::TcpListener, num::IntErrorKind, path::Path, sync::RwLock,
-    thread,
-};
-
-static INDEX_STYLE: RwLock<IndexStyle> = RwLock::new(IndexStyle::IndexDirectory);
-static DIR: RwLock<String> = RwLock::new(String::new());
+use std::{error::Error, io::BufRead, net::TcpListener, num::IntErrorKind, path::Path, thread};
 
 fn main() -> Result<(), Box<dyn Error>> {
     let mut args = std::env::args().skip(1);
 
-    // default is set here because `.to_string()` is not const
-    *DIR.write().unwrap() = "./public/".to_string();
-    // calling unwrap here is safe because the lock has not had time to be poisoned yet
-
+    let mut dir = "./public/".to_string();
+    let mut index_style = IndexStyle::IndexDirectory;
+    let mut dotfiles = DotFiles::Ignore;
+    let mut external_symlinks = false;
+    let mut compress = false;
+    let mut writable = false;
+    let mut archives = false;
+    let mut auth = None;
+    let mut cors_origins = Vec::new();
+    let mut spa = None;
+    let mut error_pages = Vec::new();
+    let mut cache = CacheRules::new();
     let mut port = 8080;
     let mut external = false;
+    let mut limits = Limits::default();
+
+    let parse_size = |name: &str, value: Option<String>| -> usize {
+        match value.map(|value| value.parse::<usize>()) {
+            Some(Ok(size)) if size > 0 => size,
+            _ => invalid_argument(format!("{name} requires a positive number")),
+        }
+    };
 
     while let Some(arg) = args.next() {
         if arg == "-p" || arg == "--port" {
@@ -36,17 +50,18 @@ fn main() -> Result<(), Box<dyn Error>> {
         } else if arg == "-i" || arg == "--index" || arg == "--index-style" {
             let is = args
                 .next()
-                .expect("index style missing: (dir | [filename] | none)");
+                .expect("index style missing: (dir | [filename,...] | none)");
 
-            *INDEX_STYLE.write().expect("failed to get write lock") = {
-                match is.to_lowercase().as_str() {
-                    "dir" => IndexStyle::IndexDirectory,
-                    "none" => IndexStyle::NotFound,
-                    _ => IndexStyle::IndexFile(is),
+            index_style = match is.to_lowercase().as_str() {
+                "dir" => IndexStyle::IndexDirectory,
+                "none" => IndexStyle::NotFound,
+                _ if is.contains(',') => {
+                    IndexStyle::IndexFiles(is.split(',').map(str::to_string).collect())
                 }
+                _ => IndexStyle::IndexFile(is),
             }
         } else if arg == "--dir" || arg == "-d" {
-            let dir = args.next().expect("dir not provided");
+            dir = args.next().expect("dir not provided");
 
             {
                 let path = Path::new(&dir);
@@ -58,12 +73,81 @@ fn main() -> Result<(), Box<dyn Error>> {
                     std::process::exit(1);
                 }
             }
-
-            *DIR.write().expect("could not get write lock") = dir;
+        } else if arg == "--dotfiles" {
+            dotfiles = match args
+                .next()
+                .expect("dotfile policy missing: (allow | deny | ignore)")
+                .to_lowercase()
+                .as_str()
+            {
+                "allow" => DotFiles::Allow,
+                "deny" => DotFiles::Deny,
+                "ignore" => DotFiles::Ignore,
+                other => invalid_argument(format!("unknown dotfile policy: {other:?}")),
+            };
+        } else if arg == "--external-symlinks" {
+            external_symlinks = true;
+        } else if arg == "--spa" {
+            spa = Some(args.next().expect("spa fallback file missing"));
+        } else if arg == "--error-page" {
+            let page = args.next().expect("error page missing: [status]=[file]");
+            error_pages.push(match page.split_once('=') {
+                Some((status, file)) => match status.parse::<u16>() {
+                    Ok(status @ 400..=599) => (status, file.to_string()),
+                    _ => invalid_argument(format!(
+                        "error pages need a status between 400 and 599: {status:?}"
+                    )),
+                },
+                None => invalid_argument(format!("error page missing a file: {page:?}")),
+            });
+        } else if arg == "--cache" {
+            let rule = args.next().expect("cache rule missing: [pattern]=[value]");
+            cache = match cache.rule(&rule) {
+                Some(cache) => cache,
+                None => invalid_argument(format!("cache rule missing a value: {rule:?}")),
+            };
+        } else if arg == "--compress" {
+            compress = true;
+        } else if arg == "--writable" {
+            writable = true;
+        } else if arg == "--archives" {
+            archives = true;
+        } else if arg == "--cors" {
+            cors_origins.push(args.next().expect("cors origin missing"));
+        } else if arg == "--auth" {
+            let credentials = args.next().expect("credentials missing: [user]:[password]");
+            auth = match credentials.split_once(':') {
+                Some((user, password)) => Some(BasicAuth::credentials(user, password)),
+                None => {
+                    invalid_argument(format!("credentials missing a password: {credentials:?}"))
+                }
+            };
+        } else if arg == "--htpasswd" {
+            let file = args.next().expect("htpasswd file missing");
+            auth = match BasicAuth::htpasswd(&file) {
+                Ok(auth) => Some(auth),
+                Err(err) => {
+                    eprintln!("Could not read {file}: {err}");
+                    std::process::exit(1);
+                }
+            };
+        } else if arg == "--max-uri" {
+            limits.max_request_line = parse_size(&arg, args.next());
+        } else if arg == "--max-header-size" {
+            limits.max_header_bytes = parse_size(&arg, args.next());
+        } else if arg == "--max-headers" {
+            limits.max_headers = parse_size(&arg, args.next());
+        } else if arg == "--max-body" {
+            limits.max_body = parse_size(&arg, args.next());
         } else if arg == "--help" || arg == "-h" {
             eprint!(
                 include_str!("./help.txt"),
-                bin = std::env::args().next().unwrap().split('/').last().unwrap(),
+                bin = std::env::args()
+                    .next()
+                    .unwrap()
+                    .split('/')
+                    .next_back()
+                    .unwrap(),
             );
             std::process::exit(0);
         } else {
@@ -71,7 +155,16 @@ fn main() -> Result<(), Box<dyn Error>> {
         }
     }
 
-    let port_range_end = port.checked_add(9).unwrap_or(u16::MAX);
+    // any site could read the protected files with the credentials cached by the browser,
+    // or change the directory
+    if (auth.is_some() || writable) && cors_origins.iter().any(|origin| origin == "*") {
+        invalid_argument(
+            "--cors \"*\" can not be combined with --auth, --htpasswd or --writable, list the origins"
+                .to_string(),
+        );
+    }
+
+    let port_range_end = port.saturating_add(9);
     thread::spawn(move || {
         let listener = match TcpListener::bind(
             &(port..=port_range_end)
@@ -103,19 +196,51 @@ fn main() -> Result<(), Box<dyn Error>> {
             }
         };
 
-        for stream in listener.incoming() {
-            match stream {
-                Ok(stream) => {
-                    thread::spawn(move || {
-                        let req: Request = stream.try_into().expect("Failed to parse request");
-                        handler(&req).respond_to(req).expect("Failed to respond");
-                    });
-                }
-                Err(e) => {
-                    println!("Failed to read stream, {e:?}");
-                }
-            }
+        let mut options = FsOptions::new(index_style)
+            .dotfiles(dotfiles)
+            .external_symlinks(external_symlinks)
+            .cache(cache)
+            .archives(archives)
+            .writable(writable);
+        if let Some(spa) = spa {
+            options = options.spa_fallback(spa);
+        }
+        let handler = fs_handler_with(&dir, options);
+
+        let compression = compression(Compression::new());
+        let auth = auth.map(basic_auth);
+        let cors = (!cors_origins.is_empty()).then(|| {
+            let methods = match writable {
+                // uploads with the form of the listing only work from the same origin
+                true => &["GET", "HEAD", "PUT", "DELETE", "MKCOL"][..],
+                false => &["GET", "HEAD"][..],
+            };
+            let options = cors_origins
+                .iter()
+                .fold(Cors::new(), |options, origin| options.origin(origin))
+                .methods(methods);
+            // browsers send preflights without credentials
+            cors(match &auth {
+                Some(..) => options.credentials(),
+                None => options,
+            })
+        });
+
+        let mut server = Server::new().limits(limits).not_found(&*handler);
+        // preflights have to get through before authentication
+        if let Some(cors) = &cors {
+            server = server.wrap(&**cors);
+        }
+        if let Some(auth) = &auth {
+            server = server.wrap(&**auth);
+        }
+        if compress {
+            server = server.wrap(&*compression);
         }
+        for (status, file) in error_pages {
+            server = server.error_page(status, Path::new(&dir).join(file));
+        }
+        server.serve_listener(listener)
     });
 
     let mut stdin = std::io::stdin().lock();
@@ -134,101 +259,8 @@ fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn handler(req: &Request) -> Response {
-    assert!(req.pathname.starts_with('/'));
-
-    let path =
-        Path::new(&*DIR.read().unwrap()).join(String::from(".") + req.pathname.clone().as_str());
-
-    if !path.exists() {
-        not_found_handler_default(req)
-    } else if path.is_file() {
-        let mime_type = MimeType::get_for_path(&req.pathname);
-        match std::fs::read(path).map(String::from_utf8) {
-            Ok(Ok(file)) => Response::builder()
-                .status(200)
-                .header("Content-Type", mime_type)
-                .body(file)
-                .build(),
-            _ => Response::builder().status(500).build(),
-        }
-    } else if path.is_dir() {
-        match *INDEX_STYLE.read().expect("failed to get index style") {
-            IndexStyle::IndexDirectory => match std::fs::read_dir(path) {
-                Ok(files) => {
-                    let mut files = files
-                        .filter_map(|file| {
-                            // Some(file.ok()?.file_name().to_str()?.to_string())
-                            file.ok().map(|s| {
-                                (
-                                    s.path().is_dir(),
-                                    s.file_name().to_str().unwrap().to_string(),
-                                )
-                            })
-                        })
-                        .collect::<Vec<_>>();
-
-                    files.sort_by(|(a, a_name), (b, b_name)| {
-                        use std::cmp::Ordering as O;
-                        match (*a, *b) {
-                            (true, false) => O::Less,
-                            (false, true) => O::Greater,
-                            _ => a_name.cmp(b_name),
-                        }
-                    });
-
-                    let files = files.iter().fold(String::new(), |mut out, (is_dir, name)| {
-                        out += format!(
-                            r#"<li><a href="./{name}{trailing}">{name}{trailing}</a></li>"#,
-                            trailing = if *is_dir { "/" } else { "" }
-                        )
-                        .as_str();
-
-                        out
-                    });
-
-                    Response::builder()
-                        .status(200)
-                        .header("Content-Type", "text/html")
-                        .body(format!(
-                            r#"<!DOCTYPE html>
-<html lang="en" dir="ltr">
-<head>
-    <title>Index of {pathname}</title>
-    <style>:root{{color-scheme:light dark;}}</style>
-</head>
-<body>
-    <h1>Index of {pathname}</h1>
-    <ul>{files}</ul>
-</body>
-</html>
-"#,
-                            pathname = req.pathname
-                        ))
-                        .build()
-                }
-                Err(..) => Response::builder().status(500).build(),
-            },
-            IndexStyle::NotFound => not_found_handler_default(req),
-            IndexStyle::IndexFile(ref filename) => {
-                let path = path.join(filename);
-
-                if path.exists() && path.is_file() {
-                    let mime_type = MimeType::get_for_path(path.to_str().unwrap_or(".txt"));
-                    match std::fs::read(path).map(String::from_utf8) {
-                        Ok(Ok(file)) => Response::builder()
-                            .status(200)
-                            .header("Content-Type", mime_type)
-                            .body(file)
-                            .build(),
-                        _ => Response::builder().status(500).build(),
-                    }
-                } else {
-                    not_found_handler_default(req)
-                }
-            }
-        }
-    } else {
-        unreachable!()
-    }
+/// Report a wrong command line argument and stop
+fn invalid_argument(message: String) -> ! {
+    eprintln!("{message}");
+    std::process::exit(1);
 }
@@ -18,7 +18,7 @@ fn last_two<T, I: Iterator<Item = T>>(mut iter: I) -> (Option<T>, Option<T>) {
 }
 
 fn get_file_extension(path: &str) -> Option<String> {
-    let path = path.split('/').last()?;
+    let path = path.split('/').next_back()?;
     let split = path.split('.');
 
     let (a, b) = last_two(split);
@@ -46,9 +46,21 @@ impl MimeType {
             "jpg" | "jpeg" => "image/jpeg",
             "svg" => "image/svg+xml",
 
+            // audio/video
+            "mp3" => "audio/mpeg",
+            "ogg" => "audio/ogg",
+            "mp4" => "video/mp4",
+            "webm" => "video/webm",
+
             // text
             "html" => "text/html",
             "txt" => "text/plain",
+            "css" => "text/css",
+            "js" | "mjs" => "text/javascript",
+
+            // application
+            "json" => "application/json",
+            "wasm" => "application/wasm",
             /* fallback */ _ => "text/plain",
         }
         .to_string()
@@ -73,12 +85,12 @@ impl MimeType {
 
 #[test]
 fn test_last_two() {
-    let vec = vec![1, 2, 3, 4];
+    let vec = [1, 2, 3, 4];
     let out = last_two(vec.iter());
 
     assert_eq!(out, (Some(&3), Some(&4)));
 
-    let vec = vec![1];
+    let vec = [1];
     let out = last_two(vec.iter());
     assert_eq!(out, (None, Some(&1)));
 }
@@ -0,0 +1,472 @@
+//! Streaming `multipart/form-data` parser
+
+use crate::{
+    common::{percent_decode, Headers},
+    request::Request,
+};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io::{self, ErrorKind, Read},
+    rc::Rc,
+};
+
+/// Maximum combined size of the headers of a single part
+const MAX_PART_HEADERS: usize = 16 * 1024;
+
+/// Where the parser is in the body
+enum State {
+    /// Inside the body of the part with this index, the preamble is the body of part `0`
+    Body(usize),
+    /// Right before the delimiter after the part with this index
+    Delimiter(usize),
+    /// After the closing delimiter
+    Done,
+}
+
+/// State shared between [`Multipart`] and its [`Part`]s
+struct Parser<R> {
+    reader: R,
+    /// Bytes read from `reader` but not consumed yet
+    buf: Vec<u8>,
+    /// `\r\n--` followed by the boundary
+    delimiter: Vec<u8>,
+    eof: bool,
+    state: State,
+}
+
+impl<R: Read> Parser<R> {
+    /// Read until at least `len` bytes are buffered or the reader ends
+    fn fill(&mut self, len: usize) -> io::Result<()> {
+        let mut chunk = [0; 8192];
+        while self.buf.len() < len && !self.eof {
+            match self.reader.read(&mut chunk)? {
+                0 => self.eof = true,
+                read => self.buf.extend_from_slice(&chunk[..read]),
+            }
+        }
+        Ok(())
+    }
+
+    /// Read body bytes of the current part into `out`, `0` once the next delimiter is reached
+    fn read_body(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if !matches!(self.state, State::Body(..)) || out.is_empty() {
+            return Ok(0);
+        }
+
+        self.fill(self.delimiter.len() + out.len())?;
+        let available = match find(&self.buf, &self.delimiter) {
+            Some(0) => {
+                if let State::Body(index) = self.state {
+                    self.state = State::Delimiter(index);
+                }
+                return Ok(0);
+            }
+            Some(position) => position,
+            // a delimiter may start in the last bytes, keep them until more is read
+            None if !self.eof => self.buf.len() + 1 - self.delimiter.len(),
+            None => return Err(invalid("multipart body ends without a closing delimiter")),
+        };
+
+        let read = available.min(out.len());
+        out[..read].copy_from_slice(&self.buf[..read]);
+        self.buf.drain(..read);
+        Ok(read)
+    }
+
+    /// Skip to the next part and read its headers, [`None`] after the closing delimiter
+    fn next_part(&mut self) -> io::Result<Option<(usize, Headers)>> {
+        let index = match self.state {
+            State::Body(index) => {
+                io::copy(&mut BodyOf(self), &mut io::sink())?;
+                index + 1
+            }
+            State::Delimiter(index) => index + 1,
+            State::Done => return Ok(None),
+        };
+
+        self.fill(self.delimiter.len() + 2)?;
+        if !self.buf.starts_with(&self.delimiter) {
+            return Err(invalid("multipart body ends without a closing delimiter"));
+        }
+        self.buf.drain(..self.delimiter.len());
+
+        if self.buf.starts_with(b"--") {
+            self.state = State::Done;
+            return Ok(None);
+        }
+
+        // the delimiter line may end with whitespace
+        if !self
+            .read_line(MAX_PART_HEADERS)?
+            .iter()
+            .all(|c| matches!(c, b' ' | b'\t'))
+        {
+            return Err(invalid("garbage after a multipart delimiter"));
+        }
+
+        let mut headers = Headers::builder().build();
+        let mut size = 0;
+        loop {
+            let line = self.read_line(MAX_PART_HEADERS - size)?;
+            size += line.len() + 2;
+            if line.is_empty() {
+                break;
+            }
+
+            let line = String::from_utf8(line).map_err(|_| invalid("part header is not UTF-8"))?;
+            match line.split_once(':') {
+                Some((key, value)) => headers.insert(key.trim(), value.trim()),
+                None => return Err(invalid("part header without a colon")),
+            }
+        }
+
+        self.state = State::Body(index);
+        Ok(Some((index, headers)))
+    }
+
+    /// Read a line ending with CRLF, without the line ending
+    fn read_line(&mut self, max: usize) -> io::Result<Vec<u8>> {
+        loop {
+            if let Some(end) = find(&self.buf, b"\r\n") {
+                if end > max {
+                    break;
+                }
+                let line = self.buf[..end].to_vec();
+                self.buf.drain(..end + 2);
+                return Ok(line);
+            }
+            if self.buf.len() > max || self.eof {
+                break;
+            }
+            let len = self.buf.len() + 1;
+            self.fill(len)?;
+        }
+
+        Err(invalid("part headers are too long or incomplete"))
+    }
+}
+
+/// [`Read`] for the body of the current part
+struct BodyOf<'a, R>(&'a mut Parser<R>);
+
+impl<R: Read> Read for BodyOf<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read_body(buf)
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Parts of a `multipart/form-data` body, read from the body as they are iterated.
+///
+/// Every [`Part`] reads its body straight from the underlying reader, so it has to be read
+/// before moving on, advancing the iterator skips whatever is left of the previous part.
+///
+/// # Examples
+/// ```
+/// # use rust_http_server::multipart::Multipart;
+/// # use std::io::Read;
+/// let body = "--xyz\r\n\
+///     Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+///     Notes\r\n\
+///     --xyz\r\n\
+///     Content-Disposition: form-data; name=\"file\"; filename=\"notes.txt\"\r\n\
+///     Content-Type: text/plain\r\n\r\n\
+///     first line\r\nsecond line\r\n\
+///     --xyz--\r\n";
+///
+/// let mut parts = Multipart::new(body.as_bytes(), "xyz");
+///
+/// let mut title = parts.next().unwrap().unwrap();
+/// assert_eq!(title.name(), Some("title".to_string()));
+/// let mut value = String::new();
+/// title.read_to_string(&mut value).unwrap();
+/// assert_eq!(value, "Notes");
+///
+/// let file = parts.next().unwrap().unwrap();
+/// assert_eq!(file.filename(), Some("notes.txt".to_string()));
+/// assert_eq!(file.content_type(), Some("text/plain"));
+///
+/// assert!(parts.next().is_none());
+/// ```
+pub struct Multipart<R> {
+    parser: Rc<RefCell<Parser<R>>>,
+}
+
+impl<R: Read> Multipart<R> {
+    /// Parse the body read from `reader`, with the `boundary` from its `Content-Type`
+    pub fn new(reader: R, boundary: &str) -> Self {
+        Self {
+            parser: Rc::new(RefCell::new(Parser {
+                reader,
+                // the first delimiter is not preceded by a line break
+                buf: b"\r\n".to_vec(),
+                delimiter: format!("\r\n--{boundary}").into_bytes(),
+                eof: false,
+                state: State::Body(0),
+            })),
+        }
+    }
+}
+
+impl Multipart<Box<dyn Read + Send>> {
+    /// Parse the body of `req`, see [`Request::body_stream`].
+    ///
+    /// [`None`] if `req` is not `multipart/form-data` with a boundary or its body was already taken.
+    pub fn from_request(req: &Request) -> Option<Self> {
+        let boundary = boundary(req.headers.get("Content-Type")?)?;
+        Some(Self::new(req.body_stream()?, &boundary))
+    }
+}
+
+impl<R: Read> Iterator for Multipart<R> {
+    type Item = io::Result<Part<R>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.parser.borrow_mut().next_part();
+        match next {
+            Ok(Some((index, headers))) => Some(Ok(Part {
+                parser: self.parser.clone(),
+                index,
+                headers,
+            })),
+            Ok(None) => None,
+            Err(err) => {
+                // there is no way to find the next part after a malformed one
+                self.parser.borrow_mut().state = State::Done;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// A single part of a [`Multipart`] body, reading it yields the body of the part
+pub struct Part<R> {
+    parser: Rc<RefCell<Parser<R>>>,
+    index: usize,
+    /// Headers of the part
+    pub headers: Headers,
+}
+
+impl<R> Part<R> {
+    /// Parameters of the `Content-Disposition` header
+    fn disposition(&self) -> HashMap<String, String> {
+        self.headers
+            .get("Content-Disposition")
+            .map(|value| parameters(value))
+            .unwrap_or_default()
+    }
+
+    /// Name of the form field
+    pub fn name(&self) -> Option<String> {
+        self.disposition().remove("name")
+    }
+
+    /// Name of the uploaded file, as sent by the client.
+    ///
+    /// It may contain path separators, only use the part after the last one as a file name.
+    pub fn filename(&self) -> Option<String> {
+        let mut disposition = self.disposition();
+        // RFC 5987 encoding, `UTF-8''name`
+        let extended = disposition.remove("filename*").and_then(|value| {
+            let (charset, value) = value.split_once('\'')?;
+            let (_, value) = value.split_once('\'')?;
+            match charset.eq_ignore_ascii_case("utf-8") {
+                true => String::from_utf8(percent_decode(value)?).ok(),
+                false => None,
+            }
+        });
+        extended.or_else(|| disposition.remove("filename"))
+    }
+
+    /// `Content-Type` of the part, [`None`] means `text/plain`
+    pub fn content_type(&self) -> Option<&str> {
+        self.headers.get("Content-Type").map(String::as_str)
+    }
+}
+
+impl<R: Read> Read for Part<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut parser = self.parser.borrow_mut();
+        match parser.state {
+            State::Body(index) if index == self.index => parser.read_body(buf),
+            // the iterator already moved past this part
+            _ => Ok(0),
+        }
+    }
+}
+
+/// `boundary` parameter of a `multipart/form-data` content type
+fn boundary(content_type: &str) -> Option<String> {
+    let (essence, _) = content_type.split_once(';')?;
+    if !essence.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    parameters(content_type)
+        .remove("boundary")
+        .filter(|boundary| (1..=70).contains(&boundary.len()))
+}
+
+/// Whether `req` has a `multipart/form-data` body
+pub(crate) fn is_multipart(req: &Request) -> bool {
+    req.headers
+        .get("Content-Type")
+        .and_then(|content_type| boundary(content_type))
+        .is_some()
+}
+
+/// `key=value` parameters after the first `;` of a header value, keys are lowercased and
+/// quoted values unescaped (`\"` only)
+fn parameters(value: &str) -> HashMap<String, String> {
+    let mut parameters = HashMap::new();
+    let mut rest = value.split_once(';').map_or("", |(_, rest)| rest);
+
+    while let Some((key, after)) = rest.split_once('=') {
+        let key = key.trim().trim_start_matches(';').trim().to_lowercase();
+        let after = after.trim_start();
+
+        let value = if let Some(quoted) = after.strip_prefix('"') {
+            let mut value = String::new();
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    // browsers do not escape backslashes, e.g. in Windows paths
+                    '\\' if quoted[i + 1..].starts_with('"') => {
+                        chars.next();
+                        value.push('"');
+                    }
+                    '"' => {
+                        end = i + 1;
+                        break;
+                    }
+                    c => value.push(c),
+                }
+            }
+            rest = quoted[end..].split_once(';').map_or("", |(_, rest)| rest);
+            value
+        } else {
+            let (value, after) = after.split_once(';').unwrap_or((after, ""));
+            rest = after;
+            value.trim().to_string()
+        };
+
+        parameters.insert(key, value);
+    }
+
+    parameters
+}
+
+#[test]
+fn test_multipart() {
+    /// Hands out one byte per read, so delimiters are split across reads
+    struct Slow<'a>(&'a [u8]);
+
+    impl Read for Slow<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let (byte, rest) = match self.0.split_first() {
+                Some(split) => split,
+                None => return Ok(0),
+            };
+            buf[0] = *byte;
+            self.0 = rest;
+            Ok(1)
+        }
+    }
+
+    let body = b"preamble\r\n--b0undary \r\n\
+        Content-Disposition: form-data; name=\"skipped\"\r\n\r\n\
+        never read\r\n\
+        --b0undary\r\n\
+        content-disposition: form-data; name=\"upload\"; filename=\"a \\\"b\\\".bin\"; filename*=UTF-8''%C3%A9.bin\r\n\
+        Content-Type: application/octet-stream\r\n\r\n\
+        \x00\xff\r\n--b0undar\r\n\r\n\
+        --b0undary--\r\nepilogue";
+
+    let mut parts = Multipart::new(Slow(body), "b0undary");
+    let skipped = parts.next().unwrap().unwrap();
+    assert_eq!(skipped.name(), Some("skipped".to_string()));
+
+    let mut upload = parts.next().unwrap().unwrap();
+    assert_eq!(upload.name(), Some("upload".to_string()));
+    assert_eq!(upload.filename(), Some("é.bin".to_string()));
+    assert_eq!(upload.content_type(), Some("application/octet-stream"));
+    let mut data = Vec::new();
+    upload.read_to_end(&mut data).unwrap();
+    assert_eq!(data, b"\x00\xff\r\n--b0undar\r\n");
+    assert!(parts.next().is_none());
+
+    // earlier parts are empty once the iterator moved on
+    let mut data = Vec::new();
+    let mut parts = Multipart::new(&body[..], "b0undary");
+    let mut skipped = parts.next().unwrap().unwrap();
+    parts.next().unwrap().unwrap();
+    assert_eq!(skipped.read_to_end(&mut data).unwrap(), 0);
+
+    let quoted = "form-data; name=\"a;b\"; filename=\"x \\\"y\\\"\"";
+    assert_eq!(parameters(quoted)["name"], "a;b");
+    assert_eq!(parameters(quoted)["filename"], "x \"y\"");
+    assert_eq!(
+        boundary("multipart/form-data; boundary=\"--abc\""),
+        Some("--abc".to_string())
+    );
+    assert_eq!(boundary("text/plain; boundary=abc"), None);
+
+    for body in [
+        &b"--b\r\nNo colon\r\n\r\n--b--"[..],
+        b"--b\r\n\r\nunterminated",
+    ] {
+        let result = Multipart::new(body, "b").try_for_each(|part| {
+            io::copy(&mut part?, &mut io::sink())?;
+            Ok::<_, io::Error>(())
+        });
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn test_multipart_request() {
+    use crate::{request::Body, response::Response, server::Server, test_utils::exchange};
+
+    // multipart bodies are not read up front, unlike other POST bodies
+    let handler = |req: &Request| {
+        let empty = matches!(req.body, Body::Empty);
+        let names = match Multipart::from_request(req) {
+            Some(parts) => parts
+                .map(|part| part.unwrap().name().unwrap_or_default())
+                .collect::<Vec<_>>()
+                .join(","),
+            None => "none".to_string(),
+        };
+        Response::builder().body(format!("{empty} {names}")).build()
+    };
+    let server = Server::new().post("/", &handler);
+    let request = |content_type: &str, body: &str| {
+        exchange(
+            &server,
+            format!(
+                "POST / HTTP/1.1\r\nConnection: close\r\nContent-Type: {content_type}\r\n\
+                 Content-Length: {}\r\n\r\n{body}",
+                body.len()
+            )
+            .as_bytes(),
+        )
+    };
+
+    let form = "--x\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n\
+                --x\r\nContent-Disposition: form-data; name=\"b\"\r\n\r\n2\r\n--x--\r\n";
+    let response = request("multipart/form-data; boundary=x", form);
+    assert!(response.ends_with("\r\n\r\ntrue a,b"));
+
+    let response = request("application/x-www-form-urlencoded", "a=1&b=2");
+    assert!(response.ends_with("\r\n\r\nfalse none"));
+}
@@ -0,0 +1,310 @@
+//! Mapping request paths onto a directory without escaping it
+
+use crate::common::percent_decode;
+use std::{
+    fmt::Display,
+    path::{Component, Path, PathBuf},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// What happens to paths containing a segment that starts with `.`
+pub enum DotFiles {
+    /// Serve them like any other file
+    Allow,
+    /// Answer with `403 Forbidden`
+    Deny,
+    /// Pretend they do not exist (`404 Not Found`), they are also left out of directory listings
+    Ignore,
+}
+
+#[derive(Clone, Debug)]
+/// Rules applied by [`resolve`]
+pub struct PathPolicy {
+    /// How to treat dotfiles and dot-directories
+    pub dotfiles: DotFiles,
+    /// Whether symlinks may point outside of the root directory
+    pub external_symlinks: bool,
+}
+
+impl PathPolicy {
+    /// The default policy: dotfiles are ignored and symlinks have to stay inside the root
+    pub const fn new() -> Self {
+        Self {
+            dotfiles: DotFiles::Ignore,
+            external_symlinks: false,
+        }
+    }
+}
+
+impl Default for PathPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(PartialEq, Eq, Debug)]
+/// Reason a request path could not be resolved
+pub enum ResolveError {
+    /// The path is malformed, e.g. contains an invalid percent-encoding or an encoded separator
+    BadRequest,
+    /// The path leaves the root directory or is denied by the [`PathPolicy`]
+    Forbidden,
+    /// The path is hidden by the [`PathPolicy`]
+    NotFound,
+}
+
+impl ResolveError {
+    /// Status code the client should be answered with
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::BadRequest => 400,
+            Self::Forbidden => 403,
+            Self::NotFound => 404,
+        }
+    }
+}
+
+impl Display for ResolveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::BadRequest => "malformed path",
+            Self::Forbidden => "path not allowed",
+            Self::NotFound => "path hidden",
+        })
+    }
+}
+
+impl std::error::Error for ResolveError {}
+
+/// Map the request path `pathname` onto `root`.
+///
+/// Segments are percent-decoded and `.`/`..` are resolved without touching the filesystem,
+/// so the result can never be outside of `root` lexically. Unless
+/// [`PathPolicy::external_symlinks`] is set, the closest existing ancestor of the result is
+/// also canonicalized to make sure no symlink leads outside of `root`.
+///
+/// The returned path does not have to exist.
+///
+/// # Examples
+/// ```
+/// # use rust_http_server::path::{resolve, PathPolicy, ResolveError};
+/// # use std::path::Path;
+/// let policy = PathPolicy::new();
+/// let root = Path::new("./public");
+///
+/// assert_eq!(resolve(root, "/a%20b/c.txt", &policy), Ok(root.join("a b/c.txt")));
+/// assert_eq!(resolve(root, "/../etc/passwd", &policy), Err(ResolveError::Forbidden));
+/// assert_eq!(resolve(root, "/.git/config", &policy), Err(ResolveError::NotFound));
+/// ```
+pub fn resolve(root: &Path, pathname: &str, policy: &PathPolicy) -> Result<PathBuf, ResolveError> {
+    let pathname = pathname.strip_prefix('/').ok_or(ResolveError::BadRequest)?;
+
+    let mut segments = Vec::new();
+    for segment in pathname.split('/') {
+        let segment = percent_decode(segment).ok_or(ResolveError::BadRequest)?;
+        let segment = String::from_utf8(segment).map_err(|_| ResolveError::BadRequest)?;
+
+        match segment.as_str() {
+            "" | "." => continue,
+            ".." => {
+                segments.pop().ok_or(ResolveError::Forbidden)?;
+                continue;
+            }
+            _ => {}
+        }
+
+        // encoded separators, NUL bytes and things like drive prefixes
+        if segment.contains(['/', '\\', '\0'])
+            || !matches!(
+                Path::new(&segment).components().collect::<Vec<_>>()[..],
+                [Component::Normal(..)]
+            )
+        {
+            return Err(ResolveError::BadRequest);
+        }
+
+        if segment.starts_with('.') {
+            match policy.dotfiles {
+                DotFiles::Allow => {}
+                DotFiles::Deny => return Err(ResolveError::Forbidden),
+                DotFiles::Ignore => return Err(ResolveError::NotFound),
+            }
+        }
+
+        segments.push(segment);
+    }
+
+    let path = segments
+        .iter()
+        .fold(root.to_path_buf(), |path, segment| path.join(segment));
+
+    if !policy.external_symlinks {
+        let root = root.canonicalize().map_err(|_| ResolveError::NotFound)?;
+        let existing = path
+            .ancestors()
+            .find(|ancestor| ancestor.symlink_metadata().is_ok())
+            .ok_or(ResolveError::NotFound)?;
+
+        match existing.canonicalize() {
+            Ok(canonical) if canonical.starts_with(&root) => {}
+            // dangling symlinks can not be checked, so they are not followed either
+            _ => return Err(ResolveError::Forbidden),
+        }
+    }
+
+    Ok(path)
+}
+
+/// `pathname` with empty, `.` and `..` segments resolved the way [`resolve`] does, keeping the
+/// rest percent-encoded.
+///
+/// Used to build `Location` headers, where a path starting with `//` would point to another
+/// host.
+///
+/// # Examples
+/// ```
+/// # use rust_http_server::path::normalize;
+/// assert_eq!(normalize("/a//b/./c/../d"), "/a/b/d");
+/// assert_eq!(normalize("//evil.com/.."), "/");
+/// assert_eq!(normalize("/a/%2e%2e/b%20c"), "/b%20c");
+/// ```
+pub fn normalize(pathname: &str) -> String {
+    let mut segments = Vec::new();
+    for segment in pathname.split('/') {
+        let decoded = percent_decode(segment).unwrap_or_default();
+        match &decoded[..] {
+            b"" | b"." => {}
+            b".." => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+
+    format!("/{}", segments.join("/"))
+}
+
+#[cfg(test)]
+/// Root with `dir/file.txt`, a `.hidden` directory and symlinks escaping it, staying inside it
+/// and pointing nowhere
+fn test_root() -> (crate::test_utils::TempDir, PathBuf) {
+    let (dir, root) = crate::test_utils::temp_root("path");
+    let outside = dir.join("outside");
+
+    std::fs::create_dir_all(root.join("dir")).unwrap();
+    std::fs::create_dir_all(root.join(".hidden")).unwrap();
+    std::fs::write(root.join("dir/file.txt"), "file").unwrap();
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::symlink;
+        symlink(&outside, root.join("escape")).unwrap();
+        symlink(root.join("dir"), root.join("inside")).unwrap();
+        symlink(root.join("missing"), root.join("dangling")).unwrap();
+    }
+
+    (dir, root)
+}
+
+#[test]
+fn test_traversal() {
+    let (_dir, root) = test_root();
+    let policy = PathPolicy::new();
+    let resolve = |pathname| resolve(&root, pathname, &policy);
+
+    for attempt in [
+        "/..",
+        "/../outside/secret.txt",
+        "/dir/../../outside/secret.txt",
+        "/dir/../dir/../../outside/secret.txt",
+        "/%2e%2e/outside/secret.txt",
+        "/%2E%2E/outside/secret.txt",
+        "/.%2e/outside/secret.txt",
+        "/dir/%2e%2e/%2e%2e/outside/secret.txt",
+    ] {
+        assert_eq!(resolve(attempt), Err(ResolveError::Forbidden), "{attempt}");
+    }
+
+    for attempt in [
+        "/..%2foutside%2fsecret.txt",
+        "/dir%2f..%2f..%2foutside",
+        "/..%5coutside%5csecret.txt",
+        "/..\\outside\\secret.txt",
+        "/dir/file.txt%00.html",
+        "/%zz",
+        "/%",
+        "/%c0%ae%c0%ae/outside",
+        "relative/path",
+    ] {
+        assert_eq!(resolve(attempt), Err(ResolveError::BadRequest), "{attempt}");
+    }
+
+    assert_eq!(resolve("/"), Ok(root.clone()));
+    assert_eq!(resolve("//dir//file.txt"), Ok(root.join("dir/file.txt")));
+    assert_eq!(resolve("/dir/./file.txt"), Ok(root.join("dir/file.txt")));
+    assert_eq!(
+        resolve("/dir/../dir/file.txt"),
+        Ok(root.join("dir/file.txt"))
+    );
+    assert_eq!(resolve("/dir/%66ile.txt"), Ok(root.join("dir/file.txt")));
+    assert_eq!(resolve("/dir/new.txt"), Ok(root.join("dir/new.txt")));
+}
+
+#[test]
+fn test_dotfiles() {
+    let (_dir, root) = test_root();
+    let with = |dotfiles| PathPolicy {
+        dotfiles,
+        ..PathPolicy::new()
+    };
+
+    for attempt in ["/.hidden", "/.hidden/x", "/%2ehidden", "/dir/.env"] {
+        assert_eq!(
+            resolve(&root, attempt, &with(DotFiles::Ignore)),
+            Err(ResolveError::NotFound),
+            "{attempt}"
+        );
+        assert_eq!(
+            resolve(&root, attempt, &with(DotFiles::Deny)),
+            Err(ResolveError::Forbidden),
+            "{attempt}"
+        );
+        assert!(
+            resolve(&root, attempt, &with(DotFiles::Allow)).is_ok(),
+            "{attempt}"
+        );
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_symlinks() {
+    let (_dir, root) = test_root();
+    let policy = PathPolicy::new();
+
+    assert_eq!(
+        resolve(&root, "/escape/secret.txt", &policy),
+        Err(ResolveError::Forbidden)
+    );
+    assert_eq!(
+        resolve(&root, "/escape/new.txt", &policy),
+        Err(ResolveError::Forbidden)
+    );
+    assert_eq!(
+        resolve(&root, "/dangling", &policy),
+        Err(ResolveError::Forbidden)
+    );
+    assert_eq!(
+        resolve(&root, "/inside/file.txt", &policy),
+        Ok(root.join("inside/file.txt"))
+    );
+
+    let policy = PathPolicy {
+        external_symlinks: true,
+        ..policy
+    };
+    assert_eq!(
+        resolve(&root, "/escape/secret.txt", &policy),
+        Ok(root.join("escape/secret.txt"))
+    );
+}
@@ -1,10 +1,26 @@
 //! All functions relating to requests
 
-use crate::common::{Headers, Method, Search};
+use crate::{
+    common::{Headers, Method, Search, Uri, Version},
+    cookie::parse_cookies,
+    json::{JsonError, JsonValue},
+    limits::{Limits, Timeouts, MIN_RATE_GRACE},
+    multipart::is_multipart,
+    response::{Response, Status},
+    session::Session,
+};
 use std::{
+    cell::RefCell,
     collections::HashMap,
-    io::{BufRead, BufReader, Read, Write},
+    error::Error,
+    fmt::Display,
+    io::{BufRead, BufReader, Cursor, ErrorKind, Read, Write},
     net::TcpStream,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
 /// Request structure
@@ -12,14 +28,36 @@ pub struct Request {
     /// Method of the request
     pub method: Method,
     /// Pathname of the request
+    ///
+    /// The path of [`Request::uri`], or its authority for `CONNECT` requests in authority-form.
+    /// Handlers mounted with [`Server::mount`](crate::server::Server::mount) see the path without
+    /// the prefix, [`Request::uri`] keeps the original.
     pub pathname: String,
+    /// Parsed request target
+    pub uri: Uri,
+    /// HTTP version of the request
+    pub version: Version,
     /// Search string of the request
     pub search: Search,
+    /// Values of the `:name` segments in the route or mount prefix that matched the request,
+    /// percent-decoded
+    pub params: HashMap<String, String>,
     /// Headers of the request
     pub headers: Headers,
     /// Body of the request
+    ///
+    /// Only `POST` bodies are read up front, the bodies of other methods and `multipart/form-data`
+    /// bodies are left in the connection until they are taken with [`Request::body_stream`].
+    /// `POST` handlers get [`Body::Empty`] for multipart bodies, which are binary and can be
+    /// large, and read them with [`Multipart::from_request`](crate::multipart::Multipart::from_request).
     pub body: Body,
     pub(crate) stream: TcpStream,
+    /// Body that is read as it is consumed, see [`Request::body_stream`]
+    body_stream: RefCell<Option<Box<dyn Read + Send>>>,
+    /// Bytes of the body that are still in the connection
+    unread_body: Arc<AtomicU64>,
+    /// Set by the [`sessions`](crate::session::sessions) middleware
+    pub(crate) session: Option<Session>,
 }
 
 impl Write for Request {
@@ -36,24 +74,29 @@ impl Write for Request {
 pub enum Body {
     /// Body has data
     Data(String),
-    // #[cfg(feature = "streamed_response")] Streamed(TcpStream),
+    /// Body is read from a stream until it ends.
+    ///
+    /// Sent with `Transfer-Encoding: chunked` unless a `Content-Length` header is set,
+    /// HTTP/1.0 clients get the raw data and the connection is closed afterwards.
+    Stream(Box<dyn Read + Send>),
     /// Body has no data
     Empty,
 }
 
 impl Body {
-    /// Get the length of the body (`0` if [`Body::Empty`])
+    /// Get the length of the body (`0` if [`Body::Empty`] or [`Body::Stream`])
     pub fn len(&self) -> usize {
         match self {
             Self::Data(data) => data.len(),
-            Self::Empty => 0,
+            Self::Stream(..) | Self::Empty => 0,
         }
     }
 
-    /// Check if the body is empty
+    /// Check if the body is empty, a [`Body::Stream`] is never considered empty
     pub fn is_empty(&self) -> bool {
         match self {
             Self::Data(data) => data.len() == 0,
+            Self::Stream(..) => false,
             Self::Empty => true,
         }
     }
@@ -77,91 +120,772 @@ impl From<String> for Body {
     }
 }
 
-impl TryFrom<TcpStream> for Request {
-    type Error = std::convert::Infallible;
+/// Error returned when a [`Request`] could not be read from a stream
+#[derive(Debug)]
+pub enum ParseError {
+    /// The connection was closed before a request line was received
+    Closed,
+    /// No request arrived within [`Timeouts::idle`]
+    Idle,
+    /// The request did not arrive within [`Timeouts::header_read`] or [`Timeouts::body_read`],
+    /// or the body was sent slower than [`Timeouts::min_body_rate`]
+    Timeout,
+    /// Reading from the stream failed
+    Io(std::io::Error),
+    /// The request is malformed
+    BadRequest(&'static str),
+    /// The request line is longer than [`Limits::max_request_line`]
+    UriTooLong,
+    /// The headers exceed [`Limits::max_header_bytes`] or [`Limits::max_headers`]
+    HeadersTooLarge,
+    /// The body is longer than [`Limits::max_body`]
+    PayloadTooLarge,
+    /// The `Expect` header contains something other than `100-continue`
+    ExpectationFailed,
+    /// The request uses an HTTP version other than 1.0 or 1.1
+    VersionNotSupported,
+    /// The body is sent with a `Transfer-Encoding`, which is not supported
+    NotImplemented,
+}
 
-    fn try_from(value: TcpStream) -> Result<Self, Self::Error> {
-        let mut reader = BufReader::new(value);
-
-        let mut read_line = || {
-            let mut buf = String::new();
-            match reader.read_line(&mut buf) {
-                Ok(..) => {
-                    if buf.ends_with("\r\n") {
-                        buf[..buf.len() - 2].to_string()
-                    } else if buf.ends_with('\n') {
-                        buf[..buf.len() - 1].to_string()
-                    } else {
-                        buf
-                    }
-                }
-                Err(e) => panic!("{e}"),
+impl ParseError {
+    /// Status code the client should be answered with, [`None`] if no response can be sent
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Self::Closed | Self::Idle | Self::Io(..) => None,
+            Self::BadRequest(..) => Some(400),
+            Self::Timeout => Some(408),
+            Self::UriTooLong => Some(414),
+            Self::HeadersTooLarge => Some(431),
+            Self::PayloadTooLarge => Some(413),
+            Self::ExpectationFailed => Some(417),
+            Self::VersionNotSupported => Some(505),
+            Self::NotImplemented => Some(501),
+        }
+    }
+
+    /// Build the error response sent to the client, if there should be one
+    pub fn response(&self) -> Option<Response> {
+        let status = Status::from(self.status()?);
+        let body = format!("{status}\n");
+
+        Some(
+            Response::builder()
+                .status(status)
+                .header("Content-Type", "text/plain")
+                .header("Content-Length", body.len())
+                .header("Connection", "close")
+                .body(body)
+                .build(),
+        )
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Closed => write!(f, "connection closed"),
+            Self::Idle => write!(f, "connection idle for too long"),
+            Self::Timeout => write!(f, "request timed out"),
+            Self::Io(err) => write!(f, "{err}"),
+            Self::BadRequest(reason) => write!(f, "bad request: {reason}"),
+            Self::UriTooLong => write!(f, "request line too long"),
+            Self::HeadersTooLarge => write!(f, "request headers too large"),
+            Self::PayloadTooLarge => write!(f, "request body too large"),
+            Self::ExpectationFailed => write!(f, "unsupported expectation"),
+            Self::VersionNotSupported => write!(f, "unsupported HTTP version"),
+            Self::NotImplemented => write!(f, "unsupported transfer coding"),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+impl From<std::io::Error> for ParseError {
+    fn from(value: std::io::Error) -> Self {
+        match value.kind() {
+            ErrorKind::TimedOut => Self::Timeout,
+            _ => Self::Io(value),
+        }
+    }
+}
+
+/// [`TcpStream`] wrapper enforcing [`Timeouts`] on reads
+pub(crate) struct Connection {
+    stream: TcpStream,
+    timeouts: Timeouts,
+    deadline: Option<Instant>,
+    /// Start of the body and bytes read since, used for [`Timeouts::min_body_rate`]
+    body: Option<(Instant, u64)>,
+}
+
+impl Connection {
+    pub(crate) fn new(stream: TcpStream, timeouts: Timeouts) -> std::io::Result<Self> {
+        stream.set_write_timeout(timeouts.write)?;
+
+        Ok(Self {
+            stream,
+            timeouts,
+            deadline: None,
+            body: None,
+        })
+    }
+
+    fn start(&mut self, timeout: Option<Duration>) {
+        self.deadline = timeout.map(|timeout| Instant::now() + timeout);
+        self.body = None;
+    }
+
+    /// Waiting for the first byte of a request
+    pub(crate) fn start_idle(&mut self) {
+        self.start(self.timeouts.idle);
+    }
+
+    /// Receiving the request line and headers
+    pub(crate) fn start_headers(&mut self) {
+        self.start(self.timeouts.header_read);
+    }
+
+    /// Receiving the body
+    pub(crate) fn start_body(&mut self) {
+        self.start(self.timeouts.body_read);
+        self.body = Some((Instant::now(), 0));
+    }
+
+    /// The point in time at which the current read has to be given up
+    fn read_deadline(&self) -> Option<Instant> {
+        let rate_deadline = match (self.body, self.timeouts.min_body_rate) {
+            (Some((start, read)), Some(rate)) if rate > 0 => {
+                Some(start + MIN_RATE_GRACE.max(Duration::from_secs_f64(read as f64 / rate as f64)))
             }
+            _ => None,
         };
 
-        let (method, (pathname, search)) = {
-            let copy = read_line();
-            let mut line = copy.split(' ');
-
-            (line.next().unwrap().parse::<Method>().unwrap(), {
-                let path = line.next().unwrap_or_else(|| {
-                    println!(
-                        r#"ERROR: {{
-  "path is None"
-  {copy}
-}}"#
-                    );
-
-                    "/"
-                });
-                match path.split_once('?') {
-                    Some((l, r)) => (
-                        l.to_string(),
-                        r.parse().expect("Could not parse search string"),
-                    ),
-                    None => (path.to_string(), Search::default()),
+        match (self.deadline, rate_deadline) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    pub(crate) fn stream(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    /// A second handle to the same connection that keeps its deadlines
+    fn try_clone(&self) -> std::io::Result<Self> {
+        Ok(Self {
+            stream: self.stream.try_clone()?,
+            timeouts: self.timeouts.clone(),
+            deadline: self.deadline,
+            body: self.body,
+        })
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let timeout = match self.read_deadline() {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                Some(remaining) if !remaining.is_zero() => Some(remaining),
+                _ => return Err(ErrorKind::TimedOut.into()),
+            },
+            None => None,
+        };
+        self.stream.set_read_timeout(timeout)?;
+
+        let read = match self.stream.read(buf) {
+            Ok(read) => read,
+            // unix reports an expired socket timeout as `WouldBlock`
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                return Err(ErrorKind::TimedOut.into())
+            }
+            Err(err) => return Err(err),
+        };
+
+        if let Some((_, total)) = self.body.as_mut() {
+            *total += read as u64;
+        }
+
+        Ok(read)
+    }
+}
+
+/// Body of a request that is read from the connection while the handler consumes it
+struct BodyReader {
+    /// Part of the body that was already buffered together with the headers
+    buffered: Cursor<Vec<u8>>,
+    connection: Connection,
+    /// Shared with [`Request::unread_body`]
+    remaining: Arc<AtomicU64>,
+    /// Where to send `100 Continue` before the first read, if the client waits for it
+    continue_to: Option<TcpStream>,
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let remaining = self.remaining.load(Ordering::Relaxed);
+        if remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+
+        if let Some(mut stream) = self.continue_to.take() {
+            stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+            stream.flush()?;
+        }
+
+        let max =
+            usize::try_from(remaining).map_or(buf.len(), |remaining| remaining.min(buf.len()));
+        let buf = &mut buf[..max];
+        let read = match self.buffered.read(buf)? {
+            0 => self.connection.read(buf)?,
+            read => read,
+        };
+        if read == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+
+        self.remaining
+            .store(remaining - read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+/// Read a single line without its line ending.
+///
+/// Returns `Ok(None)` at the end of the stream and `Err(None)` if the line is longer than `max` bytes.
+fn read_line(reader: &mut impl BufRead, max: usize) -> Result<Option<String>, Option<ParseError>> {
+    let mut buf = Vec::new();
+    // leave room for "\r\n" so a line of exactly `max` bytes is accepted
+    let read = reader
+        .by_ref()
+        .take(max as u64 + 2)
+        .read_until(b'\n', &mut buf)
+        .map_err(|err| Some(err.into()))?;
+
+    if read == 0 {
+        return Ok(None);
+    }
+
+    if buf.ends_with(b"\n") {
+        buf.pop();
+        if buf.ends_with(b"\r") {
+            buf.pop();
+        }
+    }
+
+    if buf.len() > max {
+        return Err(None);
+    }
+
+    String::from_utf8(buf)
+        .map(Some)
+        .map_err(|_| Some(ParseError::BadRequest("line is not valid UTF-8")))
+}
+
+impl Request {
+    /// Read a request from `stream`, enforcing `limits` and `timeouts` while parsing
+    ///
+    /// Requests sent with `Expect: 100-continue` are told to continue before the body is read.
+    pub fn parse(
+        stream: TcpStream,
+        limits: &Limits,
+        timeouts: &Timeouts,
+    ) -> Result<Self, ParseError> {
+        let mut reader = BufReader::new(Connection::new(stream, timeouts.clone())?);
+
+        let mut req = Self::read_head(&mut reader, limits)?;
+        req.read_body(&mut reader)?;
+        Ok(req)
+    }
+
+    /// Read the request line and headers, leaving the body in the stream
+    pub(crate) fn read_head(
+        reader: &mut BufReader<Connection>,
+        limits: &Limits,
+    ) -> Result<Self, ParseError> {
+        reader.get_mut().start_idle();
+        match reader.fill_buf() {
+            Ok([]) => return Err(ParseError::Closed),
+            Ok(..) => reader.get_mut().start_headers(),
+            Err(err) => {
+                return Err(match err.kind() {
+                    ErrorKind::TimedOut => ParseError::Idle,
+                    _ => err.into(),
+                })
+            }
+        }
+
+        let (method, uri, version) = {
+            let line = loop {
+                match read_line(reader, limits.max_request_line) {
+                    // empty lines before the request line are ignored (RFC 9112 section 2.2)
+                    Ok(Some(line)) if line.is_empty() => continue,
+                    Ok(Some(line)) => break line,
+                    Ok(None) => return Err(ParseError::Closed),
+                    Err(err) => return Err(err.unwrap_or(ParseError::UriTooLong)),
                 }
-            })
+            };
+            let mut line = line.split(' ');
+
+            let method = match line.next() {
+                Some(method) if !method.is_empty() => method.parse::<Method>().unwrap(),
+                _ => return Err(ParseError::BadRequest("missing method")),
+            };
+
+            (
+                method.clone(),
+                {
+                    let uri = line
+                        .next()
+                        .filter(|target| !target.is_empty())
+                        .ok_or(ParseError::BadRequest("missing path"))?
+                        .parse::<Uri>()
+                        .map_err(|_| ParseError::BadRequest("invalid request target"))?;
+
+                    // authority-form is only valid for CONNECT and asterisk-form only for OPTIONS
+                    match (&method, uri.is_authority(), uri.is_asterisk()) {
+                        (Method::Connect, true, _) | (Method::Options, _, true) => {}
+                        (_, false, false) => {}
+                        _ => return Err(ParseError::BadRequest("invalid request target")),
+                    }
+
+                    uri
+                },
+                {
+                    let version = line
+                        .next()
+                        .ok_or(ParseError::BadRequest("missing version"))?;
+                    if line.next().is_some() {
+                        return Err(ParseError::BadRequest("malformed request line"));
+                    }
+                    version.parse::<Version>().map_err(|_| {
+                        match version.strip_prefix("HTTP/").map(|number| {
+                            number.split('.').all(|part| {
+                                !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit())
+                            })
+                        }) {
+                            Some(true) => ParseError::VersionNotSupported,
+                            _ => ParseError::BadRequest("malformed version"),
+                        }
+                    })?
+                },
+            )
         };
 
-        let mut headers = HashMap::new();
+        let mut headers = Headers::builder().build();
+        let mut header_bytes = 0;
+        let mut header_count = 0;
         loop {
-            let line = read_line();
+            let line = match read_line(reader, limits.max_header_bytes - header_bytes) {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(err) => return Err(err.unwrap_or(ParseError::HeadersTooLarge)),
+            };
             if line.trim().is_empty() {
                 break;
             }
-            let (h, n) = line.split_once(": ").unwrap();
-            headers.insert(h.to_owned(), n.to_owned());
-        }
-        let headers = Headers::from(headers);
-
-        let body = match method {
-            Method::Post => match headers.get("Content-Length") {
-                Some(content_length) => {
-                    let content_length = content_length
-                        .parse::<usize>()
-                        .expect("Content-Length header does not contain a number");
-
-                    let mut buf = vec![0; content_length];
-                    match reader.read_exact(&mut buf) {
-                        Ok(_) => Body::Data(
-                            String::from_utf8(buf).expect("Failed to parse body to string."),
-                        ),
-                        Err(e) => panic!("{e:?}"),
-                    }
-                }
-                None => Body::Empty,
-            },
-            _ => Body::Empty,
-        };
 
-        Ok(Self {
+            header_bytes += line.len();
+            header_count += 1;
+            if header_count > limits.max_headers {
+                return Err(ParseError::HeadersTooLarge);
+            }
+
+            let (h, n) = line
+                .split_once(':')
+                .ok_or(ParseError::BadRequest("malformed header"))?;
+            headers.append(h.trim(), n.trim());
+        }
+
+        // a request is addressed to a single host (RFC 9112 section 3.2)
+        match headers.get_all("Host") {
+            [_, _, ..] => return Err(ParseError::BadRequest("more than one Host header")),
+            [host] if !host.is_empty() && !Uri::is_valid_authority(host) => {
+                return Err(ParseError::BadRequest("invalid Host header"))
+            }
+            _ => {}
+        }
+
+        // differing lengths would let the body be framed differently than by a proxy
+        if let [first, rest @ ..] = headers.get_all("Content-Length") {
+            if rest.iter().any(|length| length != first) {
+                return Err(ParseError::BadRequest("conflicting Content-Length"));
+            }
+        }
+
+        // bodies are only framed by Content-Length, anything else would be read as the next
+        // request (RFC 9112 section 6.1)
+        if headers.get("Transfer-Encoding").is_some() {
+            return Err(match headers.get("Content-Length") {
+                Some(..) => ParseError::BadRequest("Transfer-Encoding with Content-Length"),
+                None => ParseError::NotImplemented,
+            });
+        }
+
+        let req = Self {
             method,
-            pathname,
-            search,
+            pathname: match &uri.authority {
+                Some(authority) if uri.is_authority() => authority.clone(),
+                _ => uri.path.clone(),
+            },
+            search: match uri.query.as_deref() {
+                Some(query) if !query.is_empty() => query
+                    .parse()
+                    .map_err(|_| ParseError::BadRequest("invalid search string"))?,
+                _ => Search::default(),
+            },
+            uri,
+            params: HashMap::new(),
+            version,
             headers,
-            body,
-            stream: reader.into_inner(),
+            body: Body::Empty,
+            stream: reader.get_ref().stream().try_clone()?,
+            body_stream: RefCell::new(None),
+            unread_body: Arc::new(AtomicU64::new(0)),
+            session: None,
+        };
+
+        if req
+            .content_length()?
+            .is_some_and(|length| length > limits.max_body)
+        {
+            return Err(ParseError::PayloadTooLarge);
+        }
+
+        match req.headers.get("Expect") {
+            Some(expect) if !expect.eq_ignore_ascii_case("100-continue") => {
+                Err(ParseError::ExpectationFailed)
+            }
+            _ => Ok(req),
+        }
+    }
+
+    /// Length of the body that follows the headers, [`None`] if there is none
+    fn content_length(&self) -> Result<Option<usize>, ParseError> {
+        self.headers
+            .get("Content-Length")
+            .map(|length| {
+                length
+                    .parse::<usize>()
+                    .map_err(|_| ParseError::BadRequest("invalid Content-Length"))
+            })
+            .transpose()
+    }
+
+    /// Take the body of a request that is not read up front, see [`Request::body`].
+    ///
+    /// Returns [`None`] if the request has no `Content-Length` or the body was already taken.
+    /// `100 Continue` is only sent once the body is read, and a body that is not read to the
+    /// end closes the connection after the response.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rust_http_server::{request::Request, response::Response};
+    /// # use std::io::Read;
+    /// fn length(req: &Request) -> Response {
+    ///     let mut body = Vec::new();
+    ///     match req.body_stream().map(|mut stream| stream.read_to_end(&mut body)) {
+    ///         Some(Ok(length)) => Response::builder().body(length.to_string()).build(),
+    ///         Some(Err(..)) => Response::builder().status(400).build(),
+    ///         None => Response::builder().status(411).build(),
+    ///     }
+    /// }
+    /// ```
+    pub fn body_stream(&self) -> Option<Box<dyn Read + Send>> {
+        self.body_stream.borrow_mut().take()
+    }
+
+    /// Parse the body as JSON, [`None`] unless `Content-Type` is `application/json` or a
+    /// `+json` type.
+    ///
+    /// Bodies that are not read up front are taken from [`Request::body_stream`].
+    ///
+    /// # Examples
+    /// ```
+    /// # use rust_http_server::{request::Request, response::Response};
+    /// fn create(req: &Request) -> Response {
+    ///     match req.json() {
+    ///         Some(Ok(value)) => match value.get("name").and_then(|name| name.as_str()) {
+    ///             Some(name) => Response::builder().status(201).body(name).build(),
+    ///             None => Response::builder().status(400).body("name missing").build(),
+    ///         },
+    ///         Some(Err(err)) => Response::builder().status(400).body(err.to_string()).build(),
+    ///         None => Response::builder().status(415).build(),
+    ///     }
+    /// }
+    /// ```
+    pub fn json(&self) -> Option<Result<JsonValue, JsonError>> {
+        let essence = self
+            .headers
+            .get("Content-Type")?
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+        if essence != "application/json" && !essence.ends_with("+json") {
+            return None;
+        }
+
+        if let Body::Data(body) = &self.body {
+            return Some(JsonValue::parse(body));
+        }
+
+        let mut body = Vec::new();
+        if let Some(mut stream) = self.body_stream() {
+            if stream.read_to_end(&mut body).is_err() {
+                return Some(Err(JsonError::at(
+                    &body,
+                    body.len(),
+                    "body could not be read",
+                )));
+            }
+        }
+        Some(match std::str::from_utf8(&body) {
+            Ok(body) => JsonValue::parse(body),
+            Err(err) => Err(JsonError::at(&body, err.valid_up_to(), "invalid UTF-8")),
         })
     }
+
+    /// Whether part of the body is still in the connection
+    pub(crate) fn has_unread_body(&self) -> bool {
+        self.unread_body.load(Ordering::Relaxed) > 0
+    }
+
+    /// Cookies sent with the `Cookie` header by name, the first one wins if a name appears
+    /// more than once
+    ///
+    /// # Examples
+    /// ```
+    /// # use rust_http_server::{request::Request, response::Response};
+    /// fn greet(req: &Request) -> Response {
+    ///     match req.cookies().get("name") {
+    ///         Some(name) => Response::builder().body(format!("Hello {name}")).build(),
+    ///         None => Response::builder().body("Hello").build(),
+    ///     }
+    /// }
+    /// ```
+    pub fn cookies(&self) -> HashMap<String, String> {
+        // some clients send a line per cookie
+        let cookies = self.headers.get_all("Cookie").join("; ");
+        parse_cookies(&cookies)
+    }
+
+    /// Session of the request, [`None`] unless the server uses the
+    /// [`sessions`](crate::session::sessions) middleware
+    pub fn session(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+
+    /// Host the request is addressed to, without the port
+    ///
+    /// Taken from the request target if it is in absolute-form or authority-form,
+    /// which takes precedence over the `Host` header (RFC 9112 section 3.2.2).
+    pub fn host(&self) -> Option<&str> {
+        let authority = self
+            .uri
+            .authority
+            .as_deref()
+            .or(self.headers.get("Host").map(String::as_str))
+            .filter(|authority| !authority.is_empty())?;
+
+        Some(match authority.rfind(':') {
+            Some(colon) if !authority[colon..].contains(']') => &authority[..colon],
+            _ => authority,
+        })
+    }
+
+    /// Whether the client waits for `100 Continue` before sending the body
+    ///
+    /// Always `false` for HTTP/1.0, which does not know about interim responses.
+    pub fn expects_continue(&self) -> bool {
+        self.version == Version::Http11
+            && self
+                .headers
+                .get("Expect")
+                .is_some_and(|expect| expect.eq_ignore_ascii_case("100-continue"))
+    }
+
+    /// Whether the client wants to reuse the connection for another request.
+    ///
+    /// HTTP/1.1 connections persist unless `Connection: close` is sent,
+    /// HTTP/1.0 ones only with `Connection: keep-alive`.
+    pub fn keep_alive(&self) -> bool {
+        let has_option = |option: &str| {
+            self.headers.get("Connection").is_some_and(|connection| {
+                connection
+                    .split(',')
+                    .any(|token| token.trim().eq_ignore_ascii_case(option))
+            })
+        };
+
+        match self.version {
+            Version::Http10 => has_option("keep-alive"),
+            Version::Http11 => !has_option("close"),
+        }
+    }
+
+    /// Read the body announced by the headers, sending `100 Continue` first if the client waits for it.
+    ///
+    /// Bodies of methods other than `POST` and `multipart/form-data` bodies are only prepared for
+    /// [`Request::body_stream`].
+    pub(crate) fn read_body(
+        &mut self,
+        reader: &mut BufReader<Connection>,
+    ) -> Result<(), ParseError> {
+        let content_length = match self.content_length()? {
+            Some(content_length) => content_length,
+            None => return Ok(()),
+        };
+
+        if self.method != Method::Post || is_multipart(self) {
+            let buffered = reader.buffer();
+            let buffered = buffered[..buffered.len().min(content_length)].to_vec();
+            reader.consume(buffered.len());
+
+            let mut connection = reader.get_ref().try_clone()?;
+            connection.start_body();
+            self.unread_body
+                .store(content_length as u64, Ordering::Relaxed);
+            *self.body_stream.get_mut() = Some(Box::new(BodyReader {
+                buffered: Cursor::new(buffered),
+                connection,
+                remaining: self.unread_body.clone(),
+                continue_to: match self.expects_continue() {
+                    true => Some(self.stream.try_clone()?),
+                    false => None,
+                },
+            }));
+            return Ok(());
+        }
+
+        if self.expects_continue() {
+            self.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+            self.flush()?;
+        }
+
+        reader.get_mut().start_body();
+        let mut buf = vec![0; content_length];
+        reader.read_exact(&mut buf)?;
+        self.body = Body::Data(
+            String::from_utf8(buf)
+                .map_err(|_| ParseError::BadRequest("body is not valid UTF-8"))?,
+        );
+
+        Ok(())
+    }
+}
+
+impl TryFrom<TcpStream> for Request {
+    type Error = ParseError;
+
+    /// Same as [`Request::parse`] with the default [`Limits`] and [`Timeouts`]
+    fn try_from(value: TcpStream) -> Result<Self, Self::Error> {
+        Self::parse(value, &Limits::default(), &Timeouts::default())
+    }
+}
+
+#[test]
+fn test_parse() {
+    use crate::test_utils::stream;
+
+    let req = Request::try_from(stream(
+        b"POST /submit?a=1 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello",
+    ))
+    .unwrap();
+
+    assert!(req.method == Method::Post);
+    assert_eq!(req.pathname, "/submit");
+    assert_eq!(req.version, Version::Http11);
+    assert_eq!(req.host(), Some("localhost"));
+    assert_eq!(req.search.get("a"), Some(&"1".to_string()));
+    assert_eq!(req.headers.get("host"), Some(&"localhost".to_string()));
+    assert!(matches!(req.body, Body::Data(ref body) if body == "hello"));
+
+    // repeated headers keep every value
+    let repeated = Request::try_from(stream(
+        b"GET / HTTP/1.1\r\nAccept: text/html\r\naccept: */*\r\nCookie: a=1\r\nCookie: b=2\r\n\r\n",
+    ))
+    .unwrap();
+    assert_eq!(repeated.headers.get_all("Accept"), ["text/html", "*/*"]);
+    assert_eq!(repeated.cookies().len(), 2);
+
+    // requests can be handed to other threads
+    fn assert_send<T: Send>(_: T) {}
+    assert_send(req);
+}
+
+#[test]
+fn test_targets() {
+    use crate::test_utils::stream;
+
+    let req = Request::try_from(stream(
+        b"GET http://example.com:8080/a?b=c HTTP/1.1\r\nHost: other.com\r\n\r\n",
+    ))
+    .unwrap();
+    assert_eq!(req.pathname, "/a");
+    assert_eq!(req.search.get("b"), Some(&"c".to_string()));
+    assert_eq!(req.host(), Some("example.com"));
+
+    let req = Request::try_from(stream(b"CONNECT [::1]:443 HTTP/1.1\r\n\r\n")).unwrap();
+    assert_eq!(req.pathname, "[::1]:443");
+    assert_eq!(req.host(), Some("[::1]"));
+
+    let req = Request::try_from(stream(b"OPTIONS * HTTP/1.1\r\nHost: a.com:80\r\n\r\n")).unwrap();
+    assert!(req.uri.is_asterisk());
+    assert_eq!(req.host(), Some("a.com"));
+}
+
+#[test]
+fn test_limits() {
+    use crate::test_utils::stream;
+
+    let limits = Limits {
+        max_request_line: 32,
+        max_header_bytes: 64,
+        max_headers: 2,
+        max_body: 16,
+    };
+    let status = |raw: &str| {
+        Request::parse(stream(raw.as_bytes()), &limits, &Timeouts::default())
+            .err()
+            .and_then(|err| err.status())
+    };
+
+    assert_eq!(status("GET / HTTP/1.1\r\nA: b\r\n\r\n"), None);
+    assert_eq!(
+        status(&format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(32))),
+        Some(414)
+    );
+    assert_eq!(
+        status(&format!("GET / HTTP/1.1\r\nA: {}\r\n\r\n", "b".repeat(64))),
+        Some(431)
+    );
+    assert_eq!(
+        status("GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n"),
+        Some(431)
+    );
+    // every line counts, also repeated ones
+    assert_eq!(
+        status("GET / HTTP/1.1\r\nA: 1\r\nA: 1\r\nA: 1\r\n\r\n"),
+        Some(431)
+    );
+    assert_eq!(
+        status("GET / HTTP/1.1\r\nHost: a\r\nhost: b\r\n\r\n"),
+        Some(400)
+    );
+    assert_eq!(
+        status("POST / HTTP/1.1\r\nContent-Length: 17\r\n\r\n"),
+        Some(413)
+    );
+    assert_eq!(
+        status("POST / HTTP/1.1\r\nContent-Length: x\r\n\r\n"),
+        Some(400)
+    );
+    assert_eq!(status("GET / HTTP/2.0\r\n\r\n"), Some(505));
+    assert_eq!(status("GET * HTTP/1.1\r\n\r\n"), Some(400));
+    assert_eq!(status("GET a:1 HTTP/1.1\r\n\r\n"), Some(400));
+    assert_eq!(status("GET / HTTP/1.1\r\nHost: a b\r\n\r\n"), Some(400));
+    assert_eq!(status("GET / HTTP/one\r\n\r\n"), Some(400));
+    assert_eq!(status("GET /\r\n\r\n"), Some(400));
 }
@@ -1,12 +1,14 @@
 //! All functions relating to responses
 
 use crate::{
-    common::{HeaderKey, Headers, HeadersBuilder},
+    common::{HeaderKey, Headers, HeadersBuilder, Method, Version},
+    cookie::Cookie,
+    json::JsonValue,
     request::{Body, Request},
 };
 use std::{
     fmt::Display,
-    io::{Result, Write},
+    io::{Read, Result, Write},
 };
 
 /// Status Code/Message pair
@@ -22,9 +24,32 @@ impl From<u16> for Status {
         Self {
             code,
             message: match code {
+                100 => Some("Continue"),
                 200 => Some("Ok"),
+                201 => Some("Created"),
                 204 => Some("No Content"),
+                206 => Some("Partial Content"),
+                301 => Some("Moved Permanently"),
+                303 => Some("See Other"),
+                304 => Some("Not Modified"),
+                400 => Some("Bad Request"),
+                401 => Some("Unauthorized"),
+                403 => Some("Forbidden"),
                 404 => Some("Not Found"),
+                405 => Some("Method Not Allowed"),
+                408 => Some("Request Timeout"),
+                409 => Some("Conflict"),
+                411 => Some("Length Required"),
+                412 => Some("Precondition Failed"),
+                413 => Some("Payload Too Large"),
+                414 => Some("URI Too Long"),
+                416 => Some("Range Not Satisfiable"),
+                417 => Some("Expectation Failed"),
+                431 => Some("Request Header Fields Too Large"),
+                500 => Some("Internal Server Error"),
+                501 => Some("Not Implemented"),
+                503 => Some("Service Unavailable"),
+                505 => Some("HTTP Version Not Supported"),
                 _ => None,
             }
             .map(|s| s.to_string()),
@@ -72,17 +97,100 @@ pub struct Response {
 impl Response {
     /// Same as `.respond_to()`, except it borrows [`Request`]
     pub fn respond_to_mut(self, req: &mut Request) -> Result<()> {
+        self.send(req).map(|_| ())
+    }
+
+    /// Respond to `req`, honouring its version and `Connection` header.
+    ///
+    /// Returns whether the connection can be reused for another request.
+    pub(crate) fn send(self, req: &mut Request) -> Result<bool> {
+        let keep_alive = req.keep_alive();
+        let head = req.method == Method::Head;
+        let version = req.version;
+        self.write(req, version, keep_alive, head)
+    }
+
+    /// Write the response to any [`Write`]r, used when there is no [`Request`] to respond to
+    pub fn write_to(self, writer: &mut impl Write) -> Result<()> {
+        self.write(writer, Version::Http11, false, false)
+            .map(|_| ())
+    }
+
+    fn write(
+        mut self,
+        writer: &mut impl Write,
+        version: Version,
+        keep_alive: bool,
+        head: bool,
+    ) -> Result<bool> {
+        let mut keep_alive = keep_alive
+            && !self
+                .headers
+                .get("Connection")
+                .is_some_and(|connection| connection.eq_ignore_ascii_case("close"));
+        let bodiless = matches!(self.status.code, 100..=199 | 204 | 304);
+        let has_body = !head && !bodiless;
+        let has_length = self.headers.get("Content-Length").is_some();
+
+        let chunked = match self.body {
+            Body::Stream(..) if has_length => false,
+            // HTTP/1.0 does not know chunked encoding, the end of the body is the end of the connection
+            Body::Stream(..) if version == Version::Http10 => {
+                keep_alive = false;
+                false
+            }
+            // these statuses never have a body to frame (RFC 9112 section 6.1)
+            Body::Stream(..) if bodiless => false,
+            Body::Stream(..) => {
+                self.headers.insert("Transfer-Encoding", "chunked");
+                has_body
+            }
+            ref body => {
+                if !has_length && !bodiless {
+                    self.headers.insert("Content-Length", body.len());
+                }
+                false
+            }
+        };
+
+        if !keep_alive {
+            self.headers.insert("Connection", "close");
+        } else if version == Version::Http10 {
+            self.headers.insert("Connection", "keep-alive");
+        }
+
         write!(
-            req,
-            "HTTP/1.1 {status}\n{headers}\n{body}",
+            writer,
+            "HTTP/1.1 {status}\r\n{headers}\r\n",
             status = self.status,
             headers = self.headers,
-            body = match self.body {
-                Body::Data(d) => d,
-                Body::Empty => String::new(),
+        )?;
+
+        if has_body {
+            match self.body {
+                Body::Data(data) => writer.write_all(data.as_bytes())?,
+                Body::Stream(mut stream) if chunked => {
+                    let mut buf = vec![0; 16 * 1024];
+                    loop {
+                        let read = stream.read(&mut buf)?;
+                        write!(writer, "{read:X}\r\n")?;
+                        if read == 0 {
+                            writer.write_all(b"\r\n")?;
+                            break;
+                        }
+                        writer.write_all(&buf[..read])?;
+                        writer.write_all(b"\r\n")?;
+                    }
+                }
+                Body::Stream(mut stream) => {
+                    std::io::copy(&mut stream, writer)?;
+                }
+                Body::Empty => {}
             }
-        )
-        .map(|_| ())
+        }
+        writer.flush()?;
+
+        Ok(keep_alive)
     }
 
     /// Respond to a [`Request`]
@@ -95,6 +203,7 @@ impl Response {
         ResponseBuilder {
             status: None,
             headers: Headers::builder(),
+            cookies: Vec::new(),
             body: Body::Empty,
         }
     }
@@ -104,6 +213,7 @@ impl Response {
 pub struct ResponseBuilder {
     status: Option<Status>,
     headers: HeadersBuilder,
+    cookies: Vec<Cookie>,
     body: Body,
 }
 
@@ -146,6 +256,23 @@ impl ResponseBuilder {
         self
     }
 
+    /// Add a `Set-Cookie` header for `cookie`, every cookie gets its own
+    ///
+    /// # Examples
+    /// ```
+    /// # use rust_http_server::{cookie::Cookie, response::Response};
+    /// let response = Response::builder()
+    ///     .cookie(Cookie::builder("a", "1").build().unwrap())
+    ///     .cookie(Cookie::builder("b", "2").http_only().build().unwrap())
+    ///     .build();
+    ///
+    /// assert_eq!(response.headers.get_all("Set-Cookie"), ["a=1", "b=2; HttpOnly"]);
+    /// ```
+    pub fn cookie(mut self, cookie: Cookie) -> Self {
+        self.cookies.push(cookie);
+        self
+    }
+
     /// Set the body of the response.
     ///
     /// `body` can be `()` or anything that implements [`ToString`]
@@ -154,11 +281,32 @@ impl ResponseBuilder {
         self
     }
 
+    /// Set the body to `value` serialized as JSON and `Content-Type` to `application/json`
+    ///
+    /// # Examples
+    /// ```
+    /// # use rust_http_server::{json::JsonValue, response::Response};
+    /// let response = Response::builder()
+    ///     .json(JsonValue::object([("ok", true.into())]))
+    ///     .build();
+    ///
+    /// assert_eq!(response.headers.get("Content-Type").unwrap(), "application/json");
+    /// ```
+    pub fn json(self, value: impl Into<JsonValue>) -> Self {
+        self.header("Content-Type", "application/json")
+            .body(value.into().to_string())
+    }
+
     /// Construct a [`Response`]
     pub fn build(self) -> Response {
+        let mut headers = self.headers.build();
+        for cookie in self.cookies {
+            headers.append("Set-Cookie", cookie);
+        }
+
         Response {
             body: self.body,
-            headers: self.headers.build(),
+            headers,
             status: self.status.unwrap_or_else(|| Status {
                 code: 200,
                 message: Some("Ok".to_string()),
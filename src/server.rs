@@ -1,14 +1,18 @@
 //! DIY server
 
 use crate::{
-    common::{Handler, Method},
-    handlers::not_found_handler_default,
-    request::Request,
+    common::{percent_decode, Handler, Method},
+    handlers::{error_page_default, not_found_handler_default, BodyHook, Middleware},
+    limits::{Limits, Timeouts},
+    mime_types::MimeType,
+    request::{Connection, ParseError, Request},
     response::Response,
 };
 use std::{
     collections::HashMap,
-    hash::{Hash, Hasher},
+    io::BufReader,
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
     thread,
     time::Duration,
 };
@@ -28,31 +32,81 @@ impl PartialEq for Route {
 
 impl Eq for Route {}
 
-impl PartialEq<(Method, String)> for Route {
-    fn eq(&self, (method, path): &(Method, String)) -> bool {
-        (self.method.is_none() || self.method == Some(method.clone()))
-            && if self.case_sensitive {
-                &self.path == path
-            } else {
-                self.path.to_lowercase() == path.to_lowercase()
-            }
+impl Route {
+    /// Params captured from `path` if the route matches `method` and `path`
+    fn matches(&self, method: &Method, path: &str) -> Option<HashMap<String, String>> {
+        let method_matches = self.method.is_none()
+            || self.method.as_ref() == Some(method)
+            // HEAD is answered like GET, the body is left out when responding
+            || (self.method == Some(Method::Get) && method == &Method::Head);
+        if !method_matches {
+            return None;
+        }
+
+        match match_segments(&self.path, path, self.case_sensitive)? {
+            (params, rest) if rest.is_empty() => Some(params),
+            _ => None,
+        }
     }
 }
 
-impl Hash for Route {
-    fn hash<H>(&self, hasher: &mut H)
-    where
-        H: Hasher,
-    {
-        self.path.to_lowercase().hash(hasher)
+/// Match the leading segments of `path` against `pattern`, `:name` segments match any non-empty
+/// segment and capture it.
+///
+/// Returns the captured params and the rest of `path` after the matched segments, which is
+/// empty or starts with `/`.
+fn match_segments(
+    pattern: &str,
+    path: &str,
+    case_sensitive: bool,
+) -> Option<(HashMap<String, String>, String)> {
+    let mut params = HashMap::new();
+    let mut segments = path.split('/');
+
+    for expected in pattern.split('/') {
+        let segment = segments.next()?;
+        match expected.strip_prefix(':') {
+            Some(name) if !segment.is_empty() => {
+                let value = percent_decode(segment)
+                    .map(|value| String::from_utf8_lossy(&value).into_owned())
+                    .unwrap_or(segment.to_string());
+                params.insert(name.to_string(), value);
+            }
+            Some(..) => return None,
+            None if case_sensitive && expected == segment => {}
+            None if !case_sensitive && expected.to_lowercase() == segment.to_lowercase() => {}
+            None => return None,
+        }
     }
+
+    let rest = segments.fold(String::new(), |rest, segment| rest + "/" + segment);
+    Some((params, rest))
+}
+
+/// Handler registered with [`Server::mount`]
+struct Mount<'a> {
+    prefix: String,
+    handler: &'a Handler,
+}
+
+/// Page registered for an error status with [`Server::error_page`] or [`Server::error_handler`]
+enum ErrorPage<'a> {
+    File(PathBuf),
+    Handler(&'a Handler),
 }
 
 #[derive(Default)]
 /// Simple server implementation
 pub struct Server<'a> {
-    routes: HashMap<Route, &'a Handler>,
+    /// In registration order, which breaks ties between matching routes
+    routes: Vec<(Route, &'a Handler)>,
     not_found_handler: Option<&'a Handler>,
+    body_hook: Option<&'a BodyHook>,
+    middleware: Vec<&'a Middleware>,
+    mounts: Vec<Mount<'a>>,
+    error_pages: HashMap<u16, ErrorPage<'a>>,
+    limits: Limits,
+    timeouts: Timeouts,
 }
 
 // wild.
@@ -61,28 +115,26 @@ unsafe impl<'a> Sync for Server<'a> {}
 macro_rules! method_impl {
     ($($name: ident ($exact: ident) => $method: expr;)+) => {
         $(
-            pub fn $name(mut self, path: impl ToString, handler: &'a Handler) -> Self {
-                self.routes.insert(
+            pub fn $name(self, path: impl ToString, handler: &'a Handler) -> Self {
+                self.route(
                     Route {
                         method: Some($method),
                         path: path.to_string(),
                         case_sensitive: false,
                     },
                     handler,
-                );
-                self
+                )
             }
 
-            pub fn $exact(mut self, path: impl ToString, handler: &'a Handler) -> Self {
-                self.routes.insert(
+            pub fn $exact(self, path: impl ToString, handler: &'a Handler) -> Self {
+                self.route(
                     Route {
                         method: Some($method),
                         path: path.to_string(),
                         case_sensitive: true,
                     },
                     handler,
-                );
-                self
+                )
             }
         )*
     }
@@ -106,6 +158,19 @@ impl<'a> Server<'a> {
         patch (patch_exact) => Method::Patch;
     }
 
+    /// Add `route`, replacing the handler of the same route registered before
+    fn route(mut self, route: Route, handler: &'a Handler) -> Self {
+        match self
+            .routes
+            .iter_mut()
+            .find(|(existing, _)| *existing == route)
+        {
+            Some((_, existing)) => *existing = handler,
+            None => self.routes.push((route, handler)),
+        }
+        self
+    }
+
     /// Can be used as a 404, but it's also repurposable as a catch-all!
     ///
     /// Using this while not matching on anything else will catch every incoming request.
@@ -114,23 +179,163 @@ impl<'a> Server<'a> {
         self
     }
 
-    pub fn serve(self, address: &str, port: u16) -> ! {
-        use std::net::TcpListener;
+    /// Answer every request below `prefix` with `handler`, which sees
+    /// [`Request::pathname`] without the prefix.
+    ///
+    /// `prefix` may contain `:name` segments like routes do. Requests for the prefix itself are
+    /// redirected to it with a trailing slash, so relative links keep working. Routes take
+    /// precedence over mounts, and longer prefixes over shorter ones.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rust_http_server::{handlers::{fs_handler, IndexStyle}, server::Server};
+    /// // `/static/css/main.css` is read from `./public/css/main.css`
+    /// let handler = fs_handler("./public", IndexStyle::IndexDirectory);
+    /// let server = Server::new().mount("/static", &*handler);
+    /// ```
+    pub fn mount(mut self, prefix: impl ToString, handler: &'a Handler) -> Self {
+        self.mounts.push(Mount {
+            prefix: prefix.to_string().trim_end_matches('/').to_string(),
+            handler,
+        });
+        self
+    }
+
+    /// Set the [`Limits`] enforced while parsing requests
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Set the [`Timeouts`] applied to every connection
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Run `hook` after the headers of a request are received, but before the body is.
+    ///
+    /// If the hook returns a [`Response`] it is sent instead of calling a handler and the body
+    /// is never read. Clients waiting on `Expect: 100-continue` will not send it at all.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rust_http_server::{server::Server, response::Response};
+    /// let server = Server::new().before_body(&|req| {
+    ///     if req.headers.get("Authorization").is_none() {
+    ///         Some(Response::builder().status(401).build())
+    ///     } else {
+    ///         None
+    ///     }
+    /// });
+    /// ```
+    pub fn before_body(mut self, hook: &'a BodyHook) -> Self {
+        self.body_hook = Some(hook);
+        self
+    }
 
+    /// Wrap every handler, including the not found one, in `middleware`.
+    ///
+    /// Middleware added first is the outermost, so it sees the request first and the response last.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rust_http_server::server::Server;
+    /// let server = Server::new().wrap(&|req, next| {
+    ///     let mut response = next(req);
+    ///     response.headers.insert("X-Powered-By", "rust-http-server");
+    ///     response
+    /// });
+    /// ```
+    pub fn wrap(mut self, middleware: &'a Middleware) -> Self {
+        self.middleware.push(middleware);
+        self
+    }
+
+    /// Answer errors with `status` with the contents of `file`.
+    ///
+    /// The file is read for every error, so it can change while the server runs. Error pages
+    /// replace the body of every response with that status, errors without a page of their own
+    /// get a built-in one unless their handler gave them a body.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rust_http_server::server::Server;
+    /// let server = Server::new().error_page(404, "./public/404.html");
+    /// ```
+    pub fn error_page(mut self, status: u16, file: impl Into<PathBuf>) -> Self {
+        self.error_pages
+            .insert(status, ErrorPage::File(file.into()));
+        self
+    }
+
+    /// Answer errors with `status` with the body and `Content-Type` `handler` responds with,
+    /// see [`Server::error_page`]
+    ///
+    /// Errors found while parsing a request have no [`Request`] to call the handler with, so they
+    /// keep their own body.
+    pub fn error_handler(mut self, status: u16, handler: &'a Handler) -> Self {
+        self.error_pages.insert(status, ErrorPage::Handler(handler));
+        self
+    }
+
+    /// Apply the registered or built-in error page to `response` if it is an error
+    fn with_error_page(&self, req: Option<&Request>, mut response: Response) -> Response {
+        let status = response.status.code;
+        if status < 400 {
+            return response;
+        }
+
+        let page = match self.error_pages.get(&status) {
+            Some(ErrorPage::File(path)) => std::fs::read_to_string(path)
+                .ok()
+                .map(|body| (MimeType::get_for_path(&path.to_string_lossy()), body.into())),
+            Some(ErrorPage::Handler(handler)) => req.map(|req| {
+                let page = handler(req);
+                let content_type = page.headers.get("Content-Type").cloned();
+                (content_type.unwrap_or("text/html".to_string()), page.body)
+            }),
+            None => None,
+        };
+        let (content_type, body) = match page {
+            Some(page) => page,
+            None if response.body.is_empty() => {
+                let page = error_page_default(status);
+                ("text/html".to_string(), page.body)
+            }
+            None => return response,
+        };
+
+        // these described the replaced body
+        for header in [
+            "Content-Length",
+            "Content-Encoding",
+            "ETag",
+            "Last-Modified",
+        ] {
+            response.headers.remove(header);
+        }
+        response.headers.insert("Content-Type", content_type);
+        response.body = body;
+        response
+    }
+
+    pub fn serve(self, address: &str, port: u16) -> ! {
         let listener = TcpListener::bind(format!("{address}:{port}")).expect("Failed to bind");
 
+        self.serve_listener(listener)
+    }
+
+    /// Same as `.serve()`, except it accepts connections on an already bound listener
+    pub fn serve_listener(self, listener: TcpListener) -> ! {
         thread::scope(|scope| {
             for stream in listener.incoming() {
-                scope.spawn(|| {
-                    let req: Request = stream
-                        .expect("failed to open stream")
-                        .try_into()
-                        .expect("failed to parse request");
-
-                    self.handle(&req)
-                        .respond_to(req)
-                        .expect("failed to send response");
-                });
+                match stream {
+                    Ok(stream) => {
+                        scope.spawn(|| self.handle_stream(stream));
+                    }
+                    Err(e) => eprintln!("failed to open stream: {e}"),
+                }
             }
         });
 
@@ -139,21 +344,445 @@ impl<'a> Server<'a> {
         }
     }
 
-    pub fn handle(&self, req: &Request) -> Response {
-        let handler = self.routes.iter().find_map(|(route, handler)| {
-            if route == &(req.method.clone(), req.pathname.clone()) {
-                Some(handler)
-            } else {
-                None
+    /// Answer requests on `stream` until the connection is closed or can not be reused
+    pub(crate) fn handle_stream(&self, stream: TcpStream) {
+        let mut writer = match stream.try_clone() {
+            Ok(writer) => writer,
+            Err(e) => return eprintln!("failed to clone stream: {e}"),
+        };
+        let mut reader = match Connection::new(stream, self.timeouts.clone()) {
+            Ok(connection) => BufReader::new(connection),
+            Err(e) => return eprintln!("failed to configure stream: {e}"),
+        };
+
+        loop {
+            match self.next_response(&mut reader) {
+                Ok((response, mut req)) => match response.send(&mut req) {
+                    Ok(true) => continue,
+                    Ok(false) => break,
+                    Err(e) => {
+                        eprintln!("failed to send response: {e}");
+                        break;
+                    }
+                },
+                Err(err) => {
+                    if let Some(response) = err.response() {
+                        let response = self.with_error_page(None, response);
+                        let _ = response.write_to(&mut writer);
+                    }
+                    break;
+                }
             }
-        });
+        }
+    }
 
-        match handler {
+    /// Read the next request from `reader` and produce its response
+    fn next_response(
+        &self,
+        reader: &mut BufReader<Connection>,
+    ) -> Result<(Response, Request), ParseError> {
+        let mut req = Request::read_head(reader, &self.limits)?;
+
+        if let Some(mut response) = self.body_hook.and_then(|hook| hook(&req)) {
+            // the body is never read, so the connection can not be reused
+            response.headers.insert("Connection", "close");
+            return Ok((self.with_error_page(Some(&req), response), req));
+        }
+
+        req.read_body(reader)?;
+        let mut response = self.run(0, &mut req);
+        if req.has_unread_body() {
+            // the rest of the body would be read as the next request
+            response.headers.insert("Connection", "close");
+        }
+        Ok((self.with_error_page(Some(&req), response), req))
+    }
+
+    /// Call the middleware at `index` and everything after it, ending with [`Server::handle`]
+    fn run(&self, index: usize, req: &mut Request) -> Response {
+        match self.middleware.get(index) {
+            Some(middleware) => middleware(req, &|req| self.run(index + 1, req)),
+            None => self.handle(req),
+        }
+    }
+
+    /// Answer `req` with the matching route, mount or the not found handler.
+    ///
+    /// Routes with `:name` segments fill in [`Request::params`], routes with fewer of them take
+    /// precedence, between those the route registered first wins.
+    ///
+    /// Unlike earlier versions this takes `&mut Request`, routes and mounts fill in
+    /// [`Request::params`] and mounts change [`Request::pathname`] while their handler runs.
+    pub fn handle(&self, req: &mut Request) -> Response {
+        let route = self
+            .routes
+            .iter()
+            .filter_map(|(route, handler)| {
+                Some((route.matches(&req.method, &req.pathname)?, handler))
+            })
+            .min_by_key(|(params, _)| params.len());
+        if let Some((params, handler)) = route {
+            req.params = params;
+            return handler(req);
+        }
+
+        let mount = self
+            .mounts
+            .iter()
+            .filter_map(|mount| Some((match_segments(&mount.prefix, &req.pathname, false)?, mount)))
+            .max_by_key(|(_, mount)| mount.prefix.split('/').count());
+        if let Some(((params, rest), mount)) = mount {
+            if rest.is_empty() {
+                let location = match &req.uri.query {
+                    Some(query) => format!("{}/?{query}", req.uri.path),
+                    None => format!("{}/", req.uri.path),
+                };
+                return Response::builder()
+                    .status(301)
+                    .header("Location", location)
+                    .build();
+            }
+
+            req.params = params;
+            let pathname = std::mem::replace(&mut req.pathname, rest);
+            let response = (mount.handler)(req);
+            req.pathname = pathname;
+            return response;
+        }
+
+        match self.not_found_handler.as_ref() {
             Some(handler) => handler(req),
-            None => match self.not_found_handler.as_ref() {
-                Some(handler) => handler(req),
-                None => not_found_handler_default(req),
-            },
+            None => not_found_handler_default(req),
         }
     }
 }
+
+#[test]
+fn test_error_pages() {
+    use crate::test_utils::*;
+
+    let dir = temp_dir("error-pages");
+    std::fs::write(dir.join("404.html"), "custom 404").unwrap();
+
+    let forbidden = |_: &Request| Response::builder().status(403).build();
+    let teapot = |_: &Request| {
+        Response::builder()
+            .status((418, "I'm a teapot"))
+            .header("Content-Type", "application/json")
+            .body(r#"{"error":"teapot"}"#)
+            .build()
+    };
+    let gone = |_: &Request| Response::builder().status(410).build();
+    let gone_page = |req: &Request| {
+        Response::builder()
+            .header("Content-Type", "text/plain")
+            .body(format!("{} is gone", req.pathname))
+            .build()
+    };
+    let server = Server::new()
+        .get("/forbidden", &forbidden)
+        .get("/teapot", &teapot)
+        .get("/gone", &gone)
+        .error_page(404, dir.join("404.html"))
+        .error_page(400, dir.join("missing.html"))
+        .error_handler(410, &gone_page);
+    let get = |path: &str| {
+        exchange(
+            &server,
+            format!("GET {path} HTTP/1.1\r\nConnection: close\r\n\r\n").as_bytes(),
+        )
+    };
+
+    let response = get("/missing");
+    assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+    assert!(response.ends_with("\r\n\r\ncustom 404"));
+    assert!(response.contains("Content-Length: 10\r\n"));
+
+    let response = get("/forbidden");
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden"));
+    assert!(response.contains("Content-Type: text/html"));
+    assert!(response.contains("<h1>403 - Forbidden</h1>"));
+
+    // errors with a body of their own keep it
+    assert!(get("/teapot").ends_with(r#"{"error":"teapot"}"#));
+
+    let response = get("/gone");
+    assert!(response.starts_with("HTTP/1.1 410"));
+    assert!(response.contains("Content-Type: text/plain"));
+    assert!(response.ends_with("/gone is gone"));
+
+    // unreadable pages fall back to the response itself
+    let response = exchange(&server, b"GET / HTTP/1.1\r\nHost: a b\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request"));
+    assert!(response.ends_with("\r\n\r\n400 Bad Request\n"));
+}
+
+#[test]
+fn test_mount() {
+    use crate::{
+        handlers::{fs_handler, IndexStyle},
+        test_utils::*,
+    };
+
+    let dir = temp_dir("mount");
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    std::fs::write(dir.join("sub/a.txt"), "a").unwrap();
+
+    let files = fs_handler(dir.to_str().unwrap(), IndexStyle::IndexDirectory);
+    let user = |req: &Request| {
+        Response::builder()
+            .body(format!("user {}", req.params["id"]))
+            .build()
+    };
+    let me = |_: &Request| Response::builder().body("me").build();
+    let user_files = |req: &Request| {
+        Response::builder()
+            .body(format!("{} of {}", req.pathname, req.params["id"]))
+            .build()
+    };
+    let server = Server::new()
+        .get("/users/:id", &user)
+        .get("/users/me", &me)
+        .mount("/static/", &*files)
+        .mount("/users/:id/files", &user_files);
+    let get = |path: &str| {
+        exchange(
+            &server,
+            format!("GET {path} HTTP/1.1\r\nConnection: close\r\n\r\n").as_bytes(),
+        )
+    };
+
+    assert!(get("/static/sub/a.txt").ends_with("\r\n\r\na"));
+    assert!(get("/sub/a.txt").starts_with("HTTP/1.1 404"));
+    assert!(get("/static").contains("Location: /static/\r\n"));
+    assert!(get("/static/sub?a=b").contains("Location: /static/sub/?a=b\r\n"));
+
+    let response = get("/static/sub/");
+    assert!(response.contains("<title>Index of /static/sub/</title>"));
+    assert!(response.contains(r#"<a href="./a.txt">a.txt</a>"#));
+    assert!(response.contains(r#"<a href="../">../</a>"#));
+    assert!(!get("/static/").contains(r#"<a href="../">../</a>"#));
+    assert!(get("/static/sub/?format=json").contains(r#""url":"/static/sub/a.txt""#));
+
+    assert!(get("/users/42").ends_with("user 42"));
+    assert!(get("/users/a%20b").ends_with("user a b"));
+    assert!(get("/users/me").ends_with("me"));
+    assert!(get("/users/").starts_with("HTTP/1.1 404"));
+    assert!(get("/users/7/files/x/y").ends_with("/x/y of 7"));
+
+    // routes with as many params are tried in registration order
+    let section = |req: &Request| {
+        Response::builder()
+            .body(format!("section {}", req.params["section"]))
+            .build()
+    };
+    for (server, expected) in [
+        (
+            Server::new()
+                .get("/users/:id", &user)
+                .get("/:section/new", &section),
+            "user new",
+        ),
+        (
+            Server::new()
+                .get("/:section/new", &section)
+                .get("/users/:id", &user),
+            "section users",
+        ),
+    ] {
+        let response = exchange(
+            &server,
+            b"GET /users/new HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.ends_with(expected), "{response}");
+    }
+}
+
+#[test]
+fn test_limit_responses() {
+    use crate::test_utils::exchange;
+
+    let server = Server::new().limits(Limits {
+        max_body: 4,
+        ..Default::default()
+    });
+
+    let response = exchange(
+        &server,
+        b"POST / HTTP/1.1\r\nContent-Length: 1000000\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 413 Payload Too Large"));
+
+    let response = exchange(&server, b"GET /missing HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+}
+
+#[test]
+fn test_timeouts() {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        time::Instant,
+    };
+
+    let server = Server::new().timeouts(Timeouts {
+        header_read: Some(Duration::from_millis(200)),
+        min_body_rate: Some(1000),
+        ..Default::default()
+    });
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let send = |raw: &[u8]| {
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        client.write_all(raw).unwrap();
+
+        let start = Instant::now();
+        thread::scope(|scope| {
+            scope.spawn(|| server.handle_stream(stream));
+        });
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        (response, start.elapsed())
+    };
+
+    // headers never finish
+    let (response, elapsed) = send(b"GET / HTTP/1.1\r\nHost: a");
+    assert!(response.starts_with("HTTP/1.1 408 Request Timeout"));
+    assert!(elapsed < Duration::from_secs(1));
+
+    // body stalls after a few bytes
+    let (response, elapsed) = send(b"POST / HTTP/1.1\r\nContent-Length: 100000\r\n\r\nabc");
+    assert!(response.starts_with("HTTP/1.1 408 Request Timeout"));
+    assert!(elapsed < Duration::from_secs(2));
+}
+
+#[test]
+fn test_expect_continue() {
+    use crate::{request::Body, test_utils::exchange};
+
+    let echo = |req: &Request| {
+        Response::builder()
+            .body(match &req.body {
+                Body::Data(data) => data.clone(),
+                _ => String::new(),
+            })
+            .build()
+    };
+    let hook = |req: &Request| match req.headers.get("Authorization") {
+        Some(..) => None,
+        None => Some(Response::builder().status(401).build()),
+    };
+    let server = Server::new().post("/", &echo).before_body(&hook);
+
+    let response = exchange(
+        &server,
+        b"POST / HTTP/1.1\r\nAuthorization: x\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\nhello",
+    );
+    assert!(response.starts_with("HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 Ok"));
+    assert!(response.ends_with("hello"));
+
+    let response = exchange(
+        &server,
+        b"POST / HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 401 Unauthorized"));
+
+    let response = exchange(
+        &server,
+        b"POST / HTTP/1.1\r\nExpect: something\r\nContent-Length: 5\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 417 Expectation Failed"));
+}
+
+#[test]
+fn test_versions() {
+    use crate::{request::Body, test_utils::exchange};
+
+    let stream = |_: &Request| {
+        Response::builder()
+            .body(Body::Stream(Box::new(&b"streamed"[..])))
+            .build()
+    };
+    let text = |_: &Request| Response::builder().body("text").build();
+    let not_modified = |_: &Request| {
+        Response::builder()
+            .status(304)
+            .body(Body::Stream(Box::new(&b""[..])))
+            .build()
+    };
+    let server = Server::new()
+        .get("/stream", &stream)
+        .get("/text", &text)
+        .get("/cached", &not_modified);
+
+    // both requests are answered on the same connection
+    let response = exchange(
+        &server,
+        b"GET /text HTTP/1.1\r\n\r\nGET /stream HTTP/1.1\r\nConnection: close\r\n\r\n",
+    );
+    let (first, second) = response.split_once("text").unwrap();
+    assert!(first.contains("Content-Length: 4\r\n"));
+    assert!(!first.contains("Connection: close"));
+    assert!(second.contains("Transfer-Encoding: chunked\r\n"));
+    assert!(second.contains("Connection: close\r\n"));
+    assert!(second.ends_with("\r\n\r\n8\r\nstreamed\r\n0\r\n\r\n"));
+
+    // HTTP/1.0 closes the connection and is never sent chunked responses
+    let response = exchange(
+        &server,
+        b"GET /stream HTTP/1.0\r\n\r\nGET /text HTTP/1.0\r\n\r\n",
+    );
+    assert!(response.contains("Connection: close\r\n"));
+    assert!(!response.contains("Transfer-Encoding"));
+    assert!(response.ends_with("\r\n\r\nstreamed"));
+
+    let response = exchange(
+        &server,
+        b"HEAD /text HTTP/1.0\r\nConnection: keep-alive\r\n\r\nGET /text HTTP/1.0\r\n\r\n",
+    );
+    let (first, second) = response.split_once("\r\n\r\n").unwrap();
+    assert!(first.contains("Connection: keep-alive"));
+    assert!(first.contains("Content-Length: 4"));
+    assert!(second.starts_with("HTTP/1.1 200 Ok"));
+    assert!(second.ends_with("\r\n\r\ntext"));
+
+    // HEAD announces the framing of the GET, bodiless statuses have none
+    let response = exchange(&server, b"HEAD /stream HTTP/1.1\r\n\r\n");
+    assert!(response.contains("Transfer-Encoding: chunked\r\n"));
+    assert!(response.ends_with("\r\n\r\n"));
+    let response = exchange(&server, b"GET /cached HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 304 Not Modified"));
+    assert!(!response.contains("Transfer-Encoding"));
+    assert!(response.ends_with("\r\n\r\n"));
+
+    let response = exchange(&server, b"GET /text HTTP/2.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 505 HTTP Version Not Supported"));
+}
+
+#[test]
+fn test_transfer_encoding() {
+    use crate::test_utils::exchange;
+
+    let text = |_: &Request| Response::builder().body("text").build();
+    let server = Server::new().get("/", &text).get("/next", &text);
+
+    // the chunked body must not be answered as a second request
+    let response = exchange(
+        &server,
+        b"GET / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nGET /next HTTP/1.1\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 501 Not Implemented"));
+    assert!(response.contains("Connection: close\r\n"));
+    assert_eq!(response.matches("HTTP/1.1").count(), 1);
+
+    for raw in [
+        &b"GET / HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n"[..],
+        b"GET / HTTP/1.1\r\nContent-Length: 0\r\nContent-Length: 23\r\n\r\nGET /next HTTP/1.1\r\n\r\n",
+    ] {
+        let response = exchange(&server, raw);
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request"));
+        assert_eq!(response.matches("HTTP/1.1").count(), 1);
+    }
+}
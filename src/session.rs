@@ -0,0 +1,365 @@
+//! Sessions identified by a signed cookie, with their data kept on the server
+
+use crate::{
+    cookie::{Cookie, SameSite},
+    crypto::{constant_time_eq, hex, hmac_sha256, random_bytes},
+    handlers::Middleware,
+    request::Request,
+    response::Response,
+};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// Data of a session
+pub type SessionData = HashMap<String, String>;
+
+/// Where session data lives between requests, shared by all connections
+pub trait SessionStore: Send + Sync {
+    /// Data of the session `id`, [`None`] if it does not exist or expired
+    fn load(&self, id: &str) -> Option<SessionData>;
+    /// Store `data` for the session `id`, which expires after `ttl` unless saved again
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration);
+    /// Remove the session `id`
+    fn remove(&self, id: &str);
+}
+
+#[derive(Default)]
+/// [`SessionStore`] keeping sessions in memory, they are lost when the server stops.
+///
+/// Expired sessions are removed when loaded, and all of them at most every quarter of the
+/// `ttl` when a session is saved.
+pub struct MemoryStore {
+    state: Mutex<MemoryState>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    sessions: HashMap<String, (Instant, SessionData)>,
+    /// When saving sweeps out expired sessions again
+    next_sweep: Option<Instant>,
+}
+
+impl MemoryStore {
+    /// An empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MemoryState> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> Option<SessionData> {
+        let sessions = &mut self.lock().sessions;
+        match sessions.get(id) {
+            Some((expires, data)) if *expires > Instant::now() => Some(data.clone()),
+            Some(..) => {
+                sessions.remove(id);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration) {
+        let mut state = self.lock();
+        let now = Instant::now();
+        if state.next_sweep.is_none_or(|next_sweep| next_sweep <= now) {
+            state.sessions.retain(|_, (expires, _)| *expires > now);
+            state.next_sweep = Some(now + ttl / 4);
+        }
+        state
+            .sessions
+            .insert(id.to_string(), (now + ttl, data.clone()));
+    }
+
+    fn remove(&self, id: &str) {
+        self.lock().sessions.remove(id);
+    }
+}
+
+/// Session of a request, see [`Request::session`].
+///
+/// Changes are saved once the response is produced.
+pub struct Session {
+    id: String,
+    data: RefCell<SessionData>,
+    /// Whether the client sent no valid session cookie
+    new: bool,
+    renew: Cell<bool>,
+    destroy: Cell<bool>,
+}
+
+impl Session {
+    /// Value of `key`
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.data.borrow().get(key).cloned()
+    }
+
+    /// Set `key` to `value`
+    pub fn insert(&self, key: impl ToString, value: impl ToString) {
+        self.data
+            .borrow_mut()
+            .insert(key.to_string(), value.to_string());
+    }
+
+    /// Remove `key`, returning its value
+    pub fn remove(&self, key: &str) -> Option<String> {
+        self.data.borrow_mut().remove(key)
+    }
+
+    /// Move the data to a new session id, call this after signing in so an id that was known
+    /// before can not be used to take over the session
+    pub fn renew(&self) {
+        self.renew.set(true);
+    }
+
+    /// Remove the session and its cookie, e.g. to sign out
+    pub fn destroy(&self) {
+        self.destroy.set(true);
+    }
+}
+
+/// Options for [`sessions`]
+///
+/// # Examples
+/// ```
+/// # use rust_http_server::{server::Server, session::{sessions, MemoryStore, Sessions}};
+/// # use std::time::Duration;
+/// let sessions = sessions(
+///     Sessions::new("a long random secret", MemoryStore::new()).ttl(Duration::from_secs(3600)),
+/// );
+/// let server = Server::new().wrap(&*sessions);
+/// ```
+pub struct Sessions {
+    secret: Vec<u8>,
+    store: Box<dyn SessionStore>,
+    cookie_name: String,
+    ttl: Duration,
+    secure: bool,
+    same_site: SameSite,
+}
+
+impl Sessions {
+    /// Sessions kept in `store`, with cookies signed with `secret`.
+    ///
+    /// The cookie is called `session`, is `HttpOnly` and `SameSite=Lax`, and sessions expire
+    /// after a day without requests.
+    pub fn new(secret: impl AsRef<[u8]>, store: impl SessionStore + 'static) -> Self {
+        Self {
+            secret: secret.as_ref().to_vec(),
+            store: Box::new(store),
+            cookie_name: "session".to_string(),
+            ttl: Duration::from_secs(24 * 60 * 60),
+            secure: false,
+            same_site: SameSite::Lax,
+        }
+    }
+
+    /// Name of the cookie
+    pub fn cookie_name(mut self, name: impl ToString) -> Self {
+        self.cookie_name = name.to_string();
+        self
+    }
+
+    /// Time after the last request at which a session expires
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Only send the cookie over HTTPS
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// `SameSite` attribute of the cookie
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = same_site;
+        self
+    }
+
+    /// `id` followed by its signature
+    fn sign(&self, id: &str) -> String {
+        format!("{id}.{}", hex(&hmac_sha256(&self.secret, id.as_bytes())))
+    }
+
+    /// Session id of a signed cookie `value`, [`None`] if the signature does not match
+    fn verify<'a>(&self, value: &'a str) -> Option<&'a str> {
+        let (id, _) = value.split_once('.')?;
+        constant_time_eq(self.sign(id).as_bytes(), value.as_bytes()).then_some(id)
+    }
+
+    fn cookie(&self, value: &str, max_age: Duration) -> Result<Cookie, crate::cookie::CookieError> {
+        let builder = Cookie::builder(&self.cookie_name, value)
+            .path("/")
+            .max_age(max_age)
+            .http_only()
+            .same_site(self.same_site);
+        match self.secure {
+            true => builder.secure().build(),
+            false => builder.build(),
+        }
+    }
+
+    /// Attach the session of `req` before calling `next`, then save it and set its cookie
+    fn apply(&self, req: &mut Request, next: &dyn Fn(&mut Request) -> Response) -> Response {
+        let existing = req
+            .cookies()
+            .get(&self.cookie_name)
+            .and_then(|value| self.verify(value))
+            .and_then(|id| Some((id.to_string(), self.store.load(id)?)));
+        let new = existing.is_none();
+        let (id, data) = existing.unwrap_or_else(|| (new_id(), SessionData::new()));
+
+        req.session = Some(Session {
+            id,
+            data: RefCell::new(data),
+            new,
+            renew: Cell::new(false),
+            destroy: Cell::new(false),
+        });
+        let mut response = next(req);
+        let session = match req.session.take() {
+            Some(session) => session,
+            None => return response,
+        };
+
+        let (cookie, id) = if session.destroy.get() {
+            if session.new {
+                return response;
+            }
+            self.store.remove(&session.id);
+            (self.cookie("", Duration::ZERO), None)
+        } else {
+            let data = session.data.into_inner();
+            // visitors without a session do not get one until something is stored
+            if session.new && data.is_empty() {
+                return response;
+            }
+
+            let id = match session.renew.get() && !session.new {
+                true => {
+                    self.store.remove(&session.id);
+                    new_id()
+                }
+                false => session.id,
+            };
+            self.store.save(&id, &data, self.ttl);
+            (self.cookie(&self.sign(&id), self.ttl), Some(id))
+        };
+
+        if let Ok(cookie) = cookie {
+            response.headers.append("Set-Cookie", cookie);
+        } else if let Some(id) = id {
+            // the client would never send the session back
+            self.store.remove(&id);
+        }
+        response
+    }
+}
+
+fn new_id() -> String {
+    hex(&random_bytes())
+}
+
+/// Middleware giving every request a [`Session`] according to `options`.
+///
+/// # Panics
+/// If the cookie name is not a valid cookie name or does not fit its `__Secure-` or `__Host-`
+/// prefix.
+pub fn sessions(options: Sessions) -> Box<Middleware> {
+    if let Err(err) = options.cookie("", options.ttl) {
+        panic!("session cookie {:?}: {err}", options.cookie_name);
+    }
+
+    Box::new(move |req, next| options.apply(req, next))
+}
+
+#[test]
+fn test_sessions() {
+    use crate::{server::Server, test_utils::exchange};
+
+    let counter = |req: &Request| {
+        let session = req.session().unwrap();
+        match req.pathname.as_str() {
+            "/count" => {
+                let count = session.get("count").map_or(0, |c| c.parse().unwrap()) + 1;
+                session.insert("count", count);
+                Response::builder().body(count.to_string()).build()
+            }
+            "/login" => {
+                session.renew();
+                Response::builder().build()
+            }
+            "/logout" => {
+                session.destroy();
+                Response::builder().build()
+            }
+            _ => Response::builder().build(),
+        }
+    };
+    let sessions = sessions(Sessions::new("secret", MemoryStore::new()));
+    let server = Server::new().wrap(&*sessions).not_found(&counter);
+    let request = |path: &str, cookie: &str| {
+        let response = exchange(
+            &server,
+            format!("GET {path} HTTP/1.1\r\nConnection: close\r\nCookie: {cookie}\r\n\r\n")
+                .as_bytes(),
+        );
+        let cookie = response
+            .lines()
+            .find_map(|line| line.strip_prefix("Set-Cookie: "))
+            .map(|cookie| cookie.split(';').next().unwrap().to_string());
+        (response, cookie)
+    };
+
+    // nothing stored, no session
+    assert_eq!(request("/", "").1, None);
+
+    let (response, cookie) = request("/count", "");
+    let cookie = cookie.unwrap();
+    assert!(response.ends_with("\r\n\r\n1"));
+    assert!(response.contains("; Path=/; Max-Age=86400; HttpOnly; SameSite=Lax\r\n"));
+    assert!(request("/count", &cookie).0.ends_with("\r\n\r\n2"));
+
+    // a tampered signature is a new session
+    let tampered = cookie.replacen("session=", "session=0", 1);
+    assert!(request("/count", &tampered).0.ends_with("\r\n\r\n1"));
+
+    let renewed = request("/login", &cookie).1.unwrap();
+    assert_ne!(renewed, cookie);
+    assert!(request("/count", &cookie).0.ends_with("\r\n\r\n1"));
+    assert!(request("/count", &renewed).0.ends_with("\r\n\r\n3"));
+
+    assert_eq!(request("/logout", &renewed).1.unwrap(), "session=");
+    assert!(request("/count", &renewed).0.ends_with("\r\n\r\n1"));
+}
+
+#[test]
+fn test_memory_store() {
+    let store = MemoryStore::new();
+    let data = SessionData::new();
+
+    store.save("a", &data, Duration::from_millis(20));
+    assert!(store.load("a").is_some());
+    std::thread::sleep(Duration::from_millis(30));
+
+    // the first save after a quarter of the ttl sweeps, later ones leave expired sessions be
+    store.save("b", &data, Duration::from_secs(3600));
+    assert!(!store.lock().sessions.contains_key("a"));
+    store.save("c", &data, Duration::ZERO);
+    store.save("d", &data, Duration::from_secs(3600));
+    assert_eq!(store.lock().sessions.len(), 3);
+
+    // but never return them
+    assert!(store.load("c").is_none());
+    assert_eq!(store.lock().sessions.len(), 2);
+}
@@ -0,0 +1,94 @@
+//! Helpers for tests that need a real connection or directory
+
+use crate::server::Server;
+use std::{
+    io::{Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+/// Directory created by [`temp_dir`], removed with everything in it when dropped
+pub struct TempDir(PathBuf);
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Create an empty directory that is unique to this test run
+pub fn temp_dir(name: &str) -> TempDir {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let dir = std::env::temp_dir().join(format!(
+        "rust-http-server-{}-{name}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    TempDir(dir)
+}
+
+/// [`temp_dir`] with an empty `root` to serve and an `outside` directory next to it holding
+/// `secret.txt`, which must never be reachable from the root.
+///
+/// Returns the temporary directory, which has to be kept alive, and the root.
+pub fn temp_root(name: &str) -> (TempDir, PathBuf) {
+    let dir = temp_dir(name);
+    let root = dir.join("root");
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::create_dir_all(dir.join("outside")).unwrap();
+    std::fs::write(dir.join("outside/secret.txt"), "secret").unwrap();
+    (dir, root)
+}
+
+/// Send `raw` over a local connection and return the server side of it
+pub fn stream(raw: &[u8]) -> TcpStream {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    client.write_all(raw).unwrap();
+    client.shutdown(Shutdown::Write).unwrap();
+
+    listener.accept().unwrap().0
+}
+
+/// Send `raw` to `server` and return everything it answered with
+pub fn exchange(server: &Server, raw: &[u8]) -> String {
+    String::from_utf8_lossy(&exchange_bytes(server, raw)).into_owned()
+}
+
+/// Same as [`exchange`], for responses that are not valid UTF-8
+pub fn exchange_bytes(server: &Server, raw: &[u8]) -> Vec<u8> {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+
+    thread::scope(|scope| {
+        scope.spawn(|| server.handle_stream(stream));
+
+        client.write_all(raw).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).unwrap();
+        response
+    })
+}
@@ -0,0 +1,315 @@
+//! Changing the served directory with `PUT`, `DELETE` and `MKCOL`
+
+use crate::{
+    common::{percent_encode, Method},
+    multipart::{is_multipart, Multipart},
+    path::{normalize, resolve, PathPolicy},
+    request::Request,
+    response::Response,
+};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, ErrorKind, Read},
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Methods a writable directory answers to, for `Allow`
+const WRITE_METHODS: &str = "GET, HEAD, PUT, DELETE, MKCOL";
+
+/// Whether `req` would change the directory
+pub(crate) fn is_write(req: &Request) -> bool {
+    match &req.method {
+        Method::Put | Method::Delete => true,
+        Method::Other(method) => method == "MKCOL",
+        _ => false,
+    }
+}
+
+/// Answer a write request for `path`, which was resolved from the request path inside `root`
+pub(crate) fn write(root: &Path, path: &Path, req: &Request) -> Response {
+    match req.method {
+        Method::Put => put(path, req),
+        Method::Delete => delete(root, path),
+        _ => mkcol(path),
+    }
+}
+
+fn status(code: u16) -> Response {
+    Response::builder().status(code).build()
+}
+
+fn error_status(err: &io::Error) -> u16 {
+    match err.kind() {
+        ErrorKind::NotFound => 404,
+        ErrorKind::PermissionDenied => 403,
+        ErrorKind::UnexpectedEof | ErrorKind::InvalidData => 400,
+        ErrorKind::TimedOut => 408,
+        _ => 500,
+    }
+}
+
+/// Write `body` to `path`, returning whether the file existed before.
+///
+/// The body is written to a temporary file next to `path` first and renamed over it once it
+/// is complete, so nobody sees a partial upload.
+fn store(path: &Path, body: &mut impl Read) -> io::Result<bool> {
+    static UPLOADS: AtomicUsize = AtomicUsize::new(0);
+
+    let (parent, name) = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => (parent, name),
+        _ => return Err(ErrorKind::InvalidInput.into()),
+    };
+    let temp = parent.join(format!(
+        ".{}.{}-{}.upload",
+        name.to_string_lossy(),
+        std::process::id(),
+        UPLOADS.fetch_add(1, Ordering::Relaxed),
+    ));
+    let existed = path.exists();
+
+    let result = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp)
+        .and_then(|mut file| io::copy(body, &mut file).and(file.sync_all()))
+        .and_then(|_| fs::rename(&temp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result.map(|_| existed)
+}
+
+/// Store the body at `path`
+fn put(path: &Path, req: &Request) -> Response {
+    if req.pathname.ends_with('/') || path.is_dir() || !path.parent().is_some_and(Path::is_dir) {
+        return status(409);
+    }
+
+    let mut body = match req.body_stream() {
+        Some(body) => body,
+        None => return status(411),
+    };
+
+    match store(path, &mut body) {
+        Ok(true) => status(204),
+        Ok(false) => status(201),
+        Err(err) => status(error_status(&err)),
+    }
+}
+
+/// Whether `req` was sent by a page of this server rather than another site.
+///
+/// Browsers send forms to other origins without asking first, unlike `PUT` which needs a
+/// preflight request, so any page could otherwise upload files. Clients that send neither
+/// `Sec-Fetch-Site` nor `Origin` are not browsers acting for another site.
+fn same_origin(req: &Request) -> bool {
+    if let Some(site) = req.headers.get("Sec-Fetch-Site") {
+        return site.eq_ignore_ascii_case("same-origin") || site.eq_ignore_ascii_case("none");
+    }
+
+    match req.headers.get("Origin") {
+        Some(origin) => origin
+            .split_once("://")
+            .zip(req.headers.get("Host"))
+            .is_some_and(|((_, authority), host)| authority.eq_ignore_ascii_case(host)),
+        None => true,
+    }
+}
+
+/// Store the files of a `multipart/form-data` body in the directory `dir`, as sent by the
+/// upload form of the listing, and send the client back to the listing.
+///
+/// Uploads from other origins are answered with `403 Forbidden`.
+pub(crate) fn upload(dir: &Path, req: &Request, policy: &PathPolicy) -> Response {
+    if !same_origin(req) {
+        return status(403);
+    }
+
+    let parts = match Multipart::from_request(req) {
+        Some(parts) => parts,
+        None if is_multipart(req) => return status(411),
+        None => return status(415),
+    };
+
+    for part in parts {
+        let mut part = match part {
+            Ok(part) => part,
+            Err(err) => return status(error_status(&err)),
+        };
+        // other fields and empty file inputs
+        let filename = match part.filename() {
+            Some(filename) => filename,
+            None => continue,
+        };
+        let name = filename.rsplit(['/', '\\']).next().unwrap_or_default();
+        if name.is_empty() {
+            continue;
+        }
+
+        let path = match resolve(dir, &format!("/{}", percent_encode(name)), policy) {
+            Ok(path) if path.parent() == Some(dir) && !path.is_dir() => path,
+            Ok(..) => return status(409),
+            Err(err) => return status(err.status()),
+        };
+        if let Err(err) = store(&path, &mut part) {
+            return status(error_status(&err));
+        }
+    }
+
+    let location = match normalize(&req.uri.path).as_str() {
+        "/" => "/".to_string(),
+        path => format!("{path}/"),
+    };
+    Response::builder()
+        .status(303)
+        .header("Location", location)
+        .build()
+}
+
+/// Remove the file or the whole directory at `path`
+fn delete(root: &Path, path: &Path) -> Response {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) => return status(error_status(&err)),
+    };
+
+    let result = if metadata.is_dir() {
+        if path == root {
+            return status(403);
+        }
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+
+    match result {
+        Ok(()) => status(204),
+        Err(err) => status(error_status(&err)),
+    }
+}
+
+/// Create the directory `path`, its parent has to exist already
+fn mkcol(path: &Path) -> Response {
+    if fs::symlink_metadata(path).is_ok() {
+        return Response::builder()
+            .status(405)
+            .header("Allow", WRITE_METHODS)
+            .build();
+    }
+
+    match fs::create_dir(path) {
+        Ok(()) => status(201),
+        Err(err) if err.kind() == ErrorKind::NotFound => status(409),
+        Err(err) => status(error_status(&err)),
+    }
+}
+
+#[test]
+fn test_writable() {
+    use crate::{
+        handlers::{fs_handler_with, FsOptions, IndexStyle},
+        server::Server,
+        test_utils::*,
+    };
+
+    let dir = temp_dir("writable");
+    let handler = fs_handler_with(
+        dir.to_str().unwrap(),
+        FsOptions::new(IndexStyle::IndexDirectory).writable(true),
+    );
+    let server = Server::new().not_found(&*handler);
+    let request = |method: &str, path: &str, body: &str| {
+        exchange(
+            &server,
+            format!(
+                "{method} {path} HTTP/1.1\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            )
+            .as_bytes(),
+        )
+    };
+
+    assert!(request("MKCOL", "/docs", "").starts_with("HTTP/1.1 201"));
+    assert!(request("MKCOL", "/docs", "").starts_with("HTTP/1.1 405"));
+    assert!(request("MKCOL", "/missing/docs", "").starts_with("HTTP/1.1 409"));
+
+    assert!(request("PUT", "/docs/a.txt", "hello").starts_with("HTTP/1.1 201"));
+    assert!(request("PUT", "/docs/a.txt", "replaced").starts_with("HTTP/1.1 204"));
+    assert_eq!(
+        fs::read_to_string(dir.join("docs/a.txt")).unwrap(),
+        "replaced"
+    );
+    assert!(request("PUT", "/missing/a.txt", "x").starts_with("HTTP/1.1 409"));
+    assert!(request("PUT", "/docs", "x").starts_with("HTTP/1.1 409"));
+    assert!(request("PUT", "/../escape.txt", "x").starts_with("HTTP/1.1 403"));
+    assert!(request("PUT", "/.hidden", "x").starts_with("HTTP/1.1 404"));
+    assert_eq!(fs::read_dir(dir.join("docs")).unwrap().count(), 1);
+
+    // the body is read from the connection, which is reused afterwards
+    let response = exchange(
+        &server,
+        b"PUT /docs/b.txt HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc\
+          GET /docs/b.txt HTTP/1.1\r\nConnection: close\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 201"));
+    assert!(response.ends_with("\r\n\r\nabc"));
+
+    // the upload form of the listing
+    assert!(request("GET", "/docs/", "").contains("enctype=\"multipart/form-data\""));
+    let form = "--x\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"C:\\tmp\\c.txt\"\r\n\r\n\
+        uploaded\r\n\
+        --x\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"\"\r\n\r\n\
+        \r\n\
+        --x--\r\n";
+    let upload = |headers: &str| {
+        exchange(
+            &server,
+            format!(
+                "POST /docs/ HTTP/1.1\r\nConnection: close\r\nHost: localhost:8080\r\n{headers}\
+                 Content-Type: multipart/form-data; boundary=x\r\nContent-Length: {}\r\n\r\n{form}",
+                form.len()
+            )
+            .as_bytes(),
+        )
+    };
+    let response = upload("Origin: http://localhost:8080\r\nSec-Fetch-Site: same-origin\r\n");
+    assert!(response.starts_with("HTTP/1.1 303"));
+    assert!(response.contains("Location: /docs/\r\n"));
+    assert_eq!(
+        fs::read_to_string(dir.join("docs/c.txt")).unwrap(),
+        "uploaded"
+    );
+
+    // forms on other sites can not upload files
+    fs::remove_file(dir.join("docs/c.txt")).unwrap();
+    for headers in [
+        "Origin: https://evil.com\r\n",
+        "Origin: null\r\n",
+        "Origin: http://localhost:8080\r\nSec-Fetch-Site: cross-site\r\n",
+    ] {
+        assert!(upload(headers).starts_with("HTTP/1.1 403"), "{headers}");
+    }
+    assert!(!dir.join("docs/c.txt").exists());
+
+    assert!(request("DELETE", "/docs/a.txt", "").starts_with("HTTP/1.1 204"));
+    assert!(request("DELETE", "/docs/a.txt", "").starts_with("HTTP/1.1 404"));
+    assert!(request("DELETE", "/docs", "").starts_with("HTTP/1.1 204"));
+    assert!(request("DELETE", "/", "").starts_with("HTTP/1.1 403"));
+    assert!(!dir.join("docs").exists());
+
+    let handler = fs_handler_with(
+        dir.to_str().unwrap(),
+        FsOptions::new(IndexStyle::IndexDirectory),
+    );
+    let server = Server::new().not_found(&*handler);
+    let response = exchange(
+        &server,
+        b"PUT /a.txt HTTP/1.1\r\nContent-Length: 1\r\n\r\nxGET / HTTP/1.1\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 405"));
+    // the unread body closes the connection
+    assert_eq!(response.matches("HTTP/1.1").count(), 1);
+}